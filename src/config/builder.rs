@@ -0,0 +1,760 @@
+use crate::config::compression::Compression;
+use crate::config::credentials_source::{CredentialsSource, Secret};
+use crate::config::key_template::{DEFAULT_KEY_TEMPLATE, KeyTemplate};
+use crate::config::retry_policy::RetryPolicy;
+use crate::config::rotation::Rotation;
+use crate::config::tracing_s3_config::{
+    DEFAULT_FLUSH_DEBOUNCE_IN_MS, DEFAULT_SHUTDOWN_TIMEOUT_IN_MS, TracingS3Config,
+};
+use crate::config::types::{
+    BufferSizeLimitKb, ChannelCapacity, CronIntervalInMs, ObjectSizeLimitMb, SpoolSizeLimitMb,
+};
+use crate::config::worker_runtime::{WorkerRuntime, WorkerThread};
+use crate::error::TracingS3Error;
+use crate::layer::event_queue::OverflowPolicy;
+use crate::layer::reentrancy_guard::DEFAULT_DENIED_TARGETS;
+use crate::sink::s3_sink::UploadStrategy;
+use crate::spool::Spool;
+use aws_sdk_s3::Client;
+use aws_types::SdkConfig;
+use aws_types::region::Region;
+use chrono_tz::Tz;
+use dotenv::dotenv;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::Level;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+/// Default log file prefix.
+pub const DEFAULT_PREFIX: &str = "logs";
+/// Default log file postfix/extension.
+pub const DEFAULT_POSTFIX: &str = "log";
+/// Default object size limit in megabytes.
+pub const DEFAULT_OBJECT_SIZE_LIMIT_MB: u64 = 100;
+/// Default flush interval in milliseconds.
+pub const DEFAULT_CRON_INTERVAL_IN_MS: u64 = 5_000;
+/// Default buffer size limit in kilobytes.
+pub const DEFAULT_BUFFER_SIZE_LIMIT_KB: u64 = 1_024;
+/// Default AWS region, used when neither the builder nor `S3_TRACING_AWS_REGION` sets one.
+pub const DEFAULT_REGION: &str = "us-west-2";
+
+/// Builder for [`TracingS3Config`], created with [`TracingS3Config::builder`].
+///
+/// Every setting is optional except the bucket, which falls back to `S3_TRACING_BUCKET` like
+/// [`TracingS3Config::new`] does. Credentials come from the access keys or the AWS default
+/// provider chain, see [`TracingS3ConfigBuilder::credentials`]. Values are
+/// validated by [`TracingS3ConfigBuilder::build`], which names the offending setter in its
+/// [`TracingS3Error`]. `Debug` output redacts the secret access key and the session token.
+#[derive(Debug, Clone, Default)]
+pub struct TracingS3ConfigBuilder {
+    region: Option<String>,
+    access_key_id: Option<String>,
+    secret_access_key: Option<Secret>,
+    session_token: Option<Secret>,
+    credentials: Option<CredentialsSource>,
+    sdk_config: Option<SdkConfig>,
+    client: Option<Client>,
+    bucket: Option<String>,
+    prefix: Option<String>,
+    postfix: Option<String>,
+    endpoint: Option<String>,
+    object_size_limit_mb: Option<u64>,
+    cron_interval_in_ms: Option<u64>,
+    buffer_size_limit_kb: Option<u64>,
+    shutdown_timeout_in_ms: Option<u64>,
+    retry_policy: Option<RetryPolicy>,
+    spool_dir: Option<PathBuf>,
+    spool_size_limit_mb: Option<u64>,
+    channel_capacity: Option<usize>,
+    overflow_policy: Option<OverflowPolicy>,
+    upload_strategy: Option<UploadStrategy>,
+    rotation: Option<Rotation>,
+    timezone: Option<Tz>,
+    key_template: Option<String>,
+    service: Option<String>,
+    compression: Option<Compression>,
+    record_separator: Option<String>,
+    denied_targets: Option<Vec<String>>,
+    current_span: bool,
+    span_list: bool,
+    span_events: Option<FmtSpan>,
+    trace_context: bool,
+    flush_on_level: Option<Level>,
+    flush_debounce_in_ms: Option<u64>,
+    worker_runtime: Option<WorkerRuntime>,
+}
+
+impl TracingS3ConfigBuilder {
+    /// Sets the AWS region. Defaults to `S3_TRACING_AWS_REGION`, or [`DEFAULT_REGION`].
+    pub fn region(mut self, region: &str) -> Self {
+        self.region = Some(region.to_string());
+        self
+    }
+
+    /// Sets the AWS access key ID. Defaults to `S3_TRACING_AWS_ACCESS_KEY_ID`.
+    pub fn access_key_id(mut self, access_key_id: &str) -> Self {
+        self.access_key_id = Some(access_key_id.to_string());
+        self
+    }
+
+    /// Sets the AWS secret access key. Defaults to `S3_TRACING_AWS_SECRET_ACCESS_KEY`.
+    pub fn secret_access_key(mut self, secret_access_key: &str) -> Self {
+        self.secret_access_key = Some(Secret(secret_access_key.to_string()));
+        self
+    }
+
+    /// Sets the session token that comes with temporary access keys.
+    /// Defaults to `S3_TRACING_AWS_SESSION_TOKEN`.
+    pub fn session_token(mut self, session_token: &str) -> Self {
+        self.session_token = Some(Secret(session_token.to_string()));
+        self
+    }
+
+    /// Sets where credentials come from, overriding the access key settings.
+    ///
+    /// Without this, access keys set on the builder or in `S3_TRACING_AWS_*` are used as
+    /// static credentials, and [`CredentialsSource::DefaultChain`] is used if there are none.
+    pub fn credentials(mut self, credentials: CredentialsSource) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Builds the S3 client from a shared `SdkConfig`, keeping its region, credentials, retry,
+    /// timeout and HTTP client settings. The region and credential setters are ignored;
+    /// [`TracingS3ConfigBuilder::endpoint`] still overrides the endpoint.
+    pub fn sdk_config(mut self, sdk_config: &SdkConfig) -> Self {
+        self.sdk_config = Some(sdk_config.clone());
+        self
+    }
+
+    /// Uses a ready-made S3 client, sharing its connection pool with the rest of the
+    /// application. The region, credential and endpoint setters are ignored.
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sets the S3 bucket name. Defaults to `S3_TRACING_BUCKET`.
+    pub fn bucket(mut self, bucket: &str) -> Self {
+        self.bucket = Some(bucket.to_string());
+        self
+    }
+
+    /// Sets the log file prefix. Defaults to [`DEFAULT_PREFIX`].
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Sets the log file postfix/extension. Defaults to [`DEFAULT_POSTFIX`].
+    pub fn postfix(mut self, postfix: &str) -> Self {
+        self.postfix = Some(postfix.to_string());
+        self
+    }
+
+    /// Sets a custom S3 endpoint, e.g. for MinIO or a local stub.
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = Some(endpoint.to_string());
+        self
+    }
+
+    /// Sets the object size limit in megabytes (1-50,000).
+    /// Defaults to [`DEFAULT_OBJECT_SIZE_LIMIT_MB`].
+    pub fn object_size_limit_mb(mut self, object_size_limit_mb: u64) -> Self {
+        self.object_size_limit_mb = Some(object_size_limit_mb);
+        self
+    }
+
+    /// Sets the flush interval in milliseconds (greater than 0).
+    /// Defaults to [`DEFAULT_CRON_INTERVAL_IN_MS`].
+    pub fn cron_interval_in_ms(mut self, cron_interval_in_ms: u64) -> Self {
+        self.cron_interval_in_ms = Some(cron_interval_in_ms);
+        self
+    }
+
+    /// Sets the buffer size limit in kilobytes (1-50,000).
+    /// Defaults to [`DEFAULT_BUFFER_SIZE_LIMIT_KB`].
+    pub fn buffer_size_limit_kb(mut self, buffer_size_limit_kb: u64) -> Self {
+        self.buffer_size_limit_kb = Some(buffer_size_limit_kb);
+        self
+    }
+
+    /// Sets how long shutting down may wait for the final upload.
+    /// Defaults to [`DEFAULT_SHUTDOWN_TIMEOUT_IN_MS`].
+    pub fn shutdown_timeout_in_ms(mut self, shutdown_timeout_in_ms: u64) -> Self {
+        self.shutdown_timeout_in_ms = Some(shutdown_timeout_in_ms);
+        self
+    }
+
+    /// Sets the retry policy used when an upload to S3 fails.
+    /// Defaults to [`RetryPolicy::default`].
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
+    /// Enables the local disk spool in `dir` (created if missing), using at most
+    /// `size_limit_mb` (1-50,000) of disk space.
+    ///
+    /// Batches that still fail after retrying are written to segment files and replayed,
+    /// oldest first, once S3 is reachable again. Segments left behind by a previous run are
+    /// recovered and replayed as well. Once the spool grows beyond `size_limit_mb`, the
+    /// oldest segments are evicted, and an "N events dropped" record is logged in their
+    /// place. If a batch cannot be written to the spool, e.g. because it is larger than
+    /// `size_limit_mb` on its own, it is kept in memory instead.
+    pub fn spool(mut self, dir: impl Into<PathBuf>, size_limit_mb: u64) -> Self {
+        self.spool_dir = Some(dir.into());
+        self.spool_size_limit_mb = Some(size_limit_mb);
+        self
+    }
+
+    /// Bounds the channel between the layer and the background worker to `channel_capacity`
+    /// events. Unbounded by default.
+    ///
+    /// Once the channel is full, the [`overflow_policy`](Self::overflow_policy) decides what
+    /// happens to new events. Dropped events are counted, and a synthetic "N events dropped"
+    /// record is written to the logs so the gap is visible.
+    pub fn channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = Some(channel_capacity);
+        self
+    }
+
+    /// Sets what happens to new events when the bounded channel is full.
+    /// Defaults to [`OverflowPolicy::DropNewest`].
+    pub fn overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = Some(overflow_policy);
+        self
+    }
+
+    /// Sets how logs are written to the bucket.
+    ///
+    /// Defaults to [`UploadStrategy::Auto`], which appends to S3 Express One Zone directory
+    /// buckets and uses multipart uploads for general-purpose buckets and other
+    /// S3-compatible stores.
+    pub fn upload_strategy(mut self, upload_strategy: UploadStrategy) -> Self {
+        self.upload_strategy = Some(upload_strategy);
+        self
+    }
+
+    /// Sets the rotation window. Defaults to the longest window the key template allows, see
+    /// [`KeyTemplate::default_rotation`]: [`Rotation::Daily`] with the default template.
+    /// Objects are rotated by size as well, see
+    /// [`object_size_limit_mb`](Self::object_size_limit_mb).
+    pub fn rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    /// Sets the timezone of rotation windows and key dates, e.g.
+    /// `chrono_tz::Europe::Berlin`. Defaults to UTC, matching the event timestamps.
+    pub fn timezone(mut self, timezone: Tz) -> Self {
+        self.timezone = Some(timezone);
+        self
+    }
+
+    /// Sets the object key template, see [`KeyTemplate`] for the supported placeholders.
+    /// Defaults to [`DEFAULT_KEY_TEMPLATE`];
+    /// [`HIVE_KEY_TEMPLATE`](crate::config::key_template::HIVE_KEY_TEMPLATE) is a Hive-style
+    /// partitioned layout.
+    pub fn key_template(mut self, key_template: &str) -> Self {
+        self.key_template = Some(key_template.to_string());
+        self
+    }
+
+    /// Sets the value of the `{service}` key template placeholder.
+    pub fn service(mut self, service: &str) -> Self {
+        self.service = Some(service.to_string());
+        self
+    }
+
+    /// Sets the compression of uploaded logs. Defaults to [`Compression::None`].
+    ///
+    /// Every upload becomes a self-contained gzip member or zstd frame, so objects built from
+    /// many appends decompress as a whole. The postfix gains the matching extension (`.gz` or
+    /// `.zst`) and `Content-Encoding` is set when an object is created.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Sets the string terminating every record, e.g. `\r\n` or `\0`. Defaults to `\n`,
+    /// producing NDJSON. Must not be empty.
+    ///
+    /// Every record is terminated (not just separated), so uploads appended to the same
+    /// object never merge the last record of one upload with the first of the next.
+    pub fn record_separator(mut self, record_separator: &str) -> Self {
+        self.record_separator = Some(record_separator.to_string());
+        self
+    }
+
+    /// Sets the event targets the layer never records. Defaults to [`DEFAULT_DENIED_TARGETS`]
+    /// (the AWS SDK and its HTTP client, see there for the matching rules), so the layer's
+    /// own uploads do not produce events that are uploaded in turn. Events raised by the
+    /// background worker itself are never recorded, whatever this list contains.
+    pub fn denied_targets(mut self, denied_targets: Vec<String>) -> Self {
+        self.denied_targets = Some(denied_targets);
+        self
+    }
+
+    /// Adds the innermost span an event happened in (its name and recorded fields) to every
+    /// record, under `span`. Disabled by default.
+    pub fn current_span(mut self, current_span: bool) -> Self {
+        self.current_span = current_span;
+        self
+    }
+
+    /// Adds the full span stack an event happened in to every record, under `spans`,
+    /// outermost span first. Each entry holds the span's name and recorded fields, including
+    /// values added later with `Span::record`. Disabled by default.
+    pub fn span_list(mut self, span_list: bool) -> Self {
+        self.span_list = span_list;
+        self
+    }
+
+    /// Adds `trace_id`, `span_id` and `trace_flags` of the span an event happened in to every
+    /// record, so logs can be correlated with traces. Disabled by default.
+    ///
+    /// With the `opentelemetry` feature the fields are read from the span's
+    /// `tracing-opentelemetry` data; otherwise tracing's own span IDs are used, with
+    /// `trace_flags` set to `00`, see
+    /// [`trace_context`](crate::layer::trace_context::trace_context).
+    pub fn trace_context(mut self, trace_context: bool) -> Self {
+        self.trace_context = trace_context;
+        self
+    }
+
+    /// Selects which points of the span lifecycle are recorded as events, like
+    /// `tracing_subscriber::fmt().with_span_events(..)`, e.g. `FmtSpan::NEW | FmtSpan::CLOSE`.
+    /// Defaults to [`FmtSpan::CLOSE`].
+    ///
+    /// The events carry `"message": "new"`, `"enter"`, `"exit"` or `"close"`. Enter, exit and
+    /// close events also carry `time.busy` (time spent inside the span) and `time.idle`
+    /// (time the span existed without being entered), both in nanoseconds.
+    pub fn span_events(mut self, span_events: FmtSpan) -> Self {
+        self.span_events = Some(span_events);
+        self
+    }
+
+    /// Uploads right away, instead of waiting for the next tick, once an event at `level` or
+    /// more severe is buffered, e.g. so an error logged just before a crash still reaches S3.
+    /// Disabled by default.
+    ///
+    /// Uploads triggered this way are at least
+    /// [`flush_debounce_in_ms`](Self::flush_debounce_in_ms) apart: events arriving sooner are
+    /// uploaded together once the debounce window ends, so a burst of errors does not turn
+    /// into one tiny append per event.
+    pub fn flush_on_level(mut self, level: Level) -> Self {
+        self.flush_on_level = Some(level);
+        self
+    }
+
+    /// Sets the minimum time between uploads triggered by `flush_on_level`, 0 to upload after
+    /// every matching event. Defaults to [`DEFAULT_FLUSH_DEBOUNCE_IN_MS`].
+    pub fn flush_debounce_in_ms(mut self, flush_debounce_in_ms: u64) -> Self {
+        self.flush_debounce_in_ms = Some(flush_debounce_in_ms);
+        self
+    }
+
+    /// Selects where the background worker runs. Defaults to [`WorkerRuntime::Ambient`], the
+    /// Tokio runtime the layer is created in.
+    ///
+    /// [`WorkerRuntime::DedicatedThread`] lets synchronous programs (or ones that set up
+    /// tracing before starting their runtime) use the layer, and keeps uploads off a
+    /// current-thread runtime, so blocking calls such as dropping the
+    /// [`ShutdownGuard`](crate::layer::shutdown_guard::ShutdownGuard) can wait for them.
+    pub fn worker_runtime(mut self, worker_runtime: WorkerRuntime) -> Self {
+        self.worker_runtime = Some(worker_runtime);
+        self
+    }
+
+    /// Like [`TracingS3ConfigBuilder::build`], for synchronous code: runs the build on the
+    /// runtime the worker will run on, so the AWS client and its credential providers are
+    /// created there. With [`WorkerRuntime::DedicatedThread`] the worker thread is started
+    /// right away and kept in the configuration as [`WorkerRuntime::Thread`]. With
+    /// [`WorkerRuntime::Handle`] the runtime must be running.
+    ///
+    /// # Returns
+    /// * `Ok(TracingS3Config)` - If every setting is valid
+    /// * `Err(TracingS3Error)` - Naming the first setting that is missing or invalid, if the
+    ///   worker runtime is [`WorkerRuntime::Ambient`], which is unknown until the layer is
+    ///   created, or if the worker thread cannot be started
+    ///
+    /// # Panics
+    /// If called from within an async context; use [`TracingS3ConfigBuilder::build`] there.
+    pub fn build_blocking(mut self) -> Result<TracingS3Config, TracingS3Error> {
+        let thread = match self.worker_runtime.clone().unwrap_or_default() {
+            WorkerRuntime::Ambient => {
+                return Err(TracingS3Error::invalid_config(
+                    "worker_runtime",
+                    "build_blocking needs the runtime the worker runs on; use \
+                     WorkerRuntime::DedicatedThread or WorkerRuntime::Handle",
+                ));
+            }
+            WorkerRuntime::Handle(handle) => return handle.block_on(self.build()),
+            WorkerRuntime::DedicatedThread => WorkerThread::start()?,
+            WorkerRuntime::Thread(thread) => thread,
+        };
+        self.worker_runtime = Some(WorkerRuntime::Thread(thread.clone()));
+        thread.handle().block_on(self.build())
+    }
+
+    /// Validates the settings and creates the configuration, including its AWS client.
+    ///
+    /// # Returns
+    /// * `Ok(TracingS3Config)` - If every setting is valid
+    /// * `Err(TracingS3Error)` - Naming the first setting that is missing or invalid
+    pub async fn build(self) -> Result<TracingS3Config, TracingS3Error> {
+        dotenv().ok();
+        let object_size_limit_mb = ObjectSizeLimitMb::new(
+            self.object_size_limit_mb
+                .unwrap_or(DEFAULT_OBJECT_SIZE_LIMIT_MB),
+        )?;
+        let cron_interval_in_ms = CronIntervalInMs::new(
+            self.cron_interval_in_ms
+                .unwrap_or(DEFAULT_CRON_INTERVAL_IN_MS),
+        )?;
+        let buffer_size_limit_kb = BufferSizeLimitKb::new(
+            self.buffer_size_limit_kb
+                .unwrap_or(DEFAULT_BUFFER_SIZE_LIMIT_KB),
+        )?;
+        let channel_capacity = self
+            .channel_capacity
+            .map(ChannelCapacity::new)
+            .transpose()?;
+        let key_template =
+            KeyTemplate::new(self.key_template.as_deref().unwrap_or(DEFAULT_KEY_TEMPLATE))?;
+        let rotation = self
+            .rotation
+            .unwrap_or_else(|| key_template.default_rotation());
+        if let Rotation::Custom(window) = rotation {
+            Rotation::custom(window)?;
+        }
+        Self::check_key_template(&key_template, self.service.as_deref(), rotation)?;
+        if let Some(record_separator) = &self.record_separator {
+            Self::check_record_separator(record_separator)?;
+        }
+        let spool = match self.spool_dir {
+            Some(dir) => Some(Self::open_spool(
+                dir,
+                SpoolSizeLimitMb::new(self.spool_size_limit_mb.unwrap_or(0))?,
+            )?),
+            None => None,
+        };
+        let bucket = Self::setting_or_env(self.bucket, "bucket", "S3_TRACING_BUCKET")?;
+        let aws_client = match (self.client, self.sdk_config) {
+            (Some(client), _) => client,
+            (None, Some(sdk_config)) => {
+                let mut config_builder = aws_sdk_s3::config::Builder::from(&sdk_config);
+                if let Some(endpoint) = self.endpoint {
+                    config_builder = config_builder.endpoint_url(endpoint);
+                }
+                Client::from_conf(config_builder.build())
+            }
+            (None, None) => {
+                let region = Region::new(
+                    self.region
+                        .or_else(|| env::var("S3_TRACING_AWS_REGION").ok())
+                        .unwrap_or(DEFAULT_REGION.to_string()),
+                );
+                let credentials = match self.credentials {
+                    Some(credentials) => credentials,
+                    None => Self::credentials_from_keys(
+                        self.access_key_id,
+                        self.secret_access_key.map(|secret| secret.0),
+                        self.session_token.map(|secret| secret.0),
+                    )?,
+                };
+                let provider = credentials.provider(region.clone()).await;
+                let mut config_builder = aws_sdk_s3::Config::builder()
+                    .behavior_version_latest()
+                    .credentials_provider(provider.clone())
+                    .region(region);
+                if credentials.is_static() {
+                    config_builder = config_builder.express_credentials_provider(provider);
+                }
+                if let Some(endpoint) = self.endpoint {
+                    config_builder = config_builder.endpoint_url(endpoint);
+                }
+                Client::from_conf(config_builder.build())
+            }
+        };
+        Ok(TracingS3Config {
+            aws_client,
+            bucket,
+            prefix: self.prefix.unwrap_or(DEFAULT_PREFIX.to_string()),
+            postfix: self.postfix.unwrap_or(DEFAULT_POSTFIX.to_string()),
+            object_size_limit_mb: object_size_limit_mb.inner(),
+            cron_interval_in_ms: cron_interval_in_ms.inner(),
+            buffer_size_limit_kb: buffer_size_limit_kb.inner(),
+            shutdown_timeout_in_ms: self
+                .shutdown_timeout_in_ms
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_IN_MS),
+            retry_policy: self.retry_policy.unwrap_or_default(),
+            spool,
+            channel_capacity: channel_capacity.map(|capacity| capacity.inner()),
+            overflow_policy: self.overflow_policy.unwrap_or(OverflowPolicy::DropNewest),
+            upload_strategy: self.upload_strategy.unwrap_or(UploadStrategy::Auto),
+            rotation,
+            timezone: self.timezone.unwrap_or(Tz::UTC),
+            key_template,
+            service: self.service,
+            compression: self.compression.unwrap_or_default(),
+            record_separator: self.record_separator.unwrap_or("\n".to_string()),
+            denied_targets: self.denied_targets.unwrap_or_else(|| {
+                DEFAULT_DENIED_TARGETS
+                    .iter()
+                    .map(|target| target.to_string())
+                    .collect()
+            }),
+            current_span: self.current_span,
+            span_list: self.span_list,
+            span_events: self.span_events.unwrap_or(FmtSpan::CLOSE),
+            trace_context: self.trace_context,
+            flush_on_level: self.flush_on_level,
+            flush_debounce_in_ms: self
+                .flush_debounce_in_ms
+                .unwrap_or(DEFAULT_FLUSH_DEBOUNCE_IN_MS),
+            worker_runtime: self.worker_runtime.unwrap_or_default(),
+        })
+    }
+
+    /// Checks that the key template can be rendered with the other settings.
+    ///
+    /// # Returns
+    /// * `Ok(())` - If the template is usable
+    /// * `Err(TracingS3Error)` - If the template uses `{service}` but no service is set, or a
+    ///   date placeholder finer than the rotation window, see [`KeyTemplate::check_rotation`]
+    pub(crate) fn check_key_template(
+        key_template: &KeyTemplate,
+        service: Option<&str>,
+        rotation: Rotation,
+    ) -> Result<(), TracingS3Error> {
+        if key_template.uses_service() && service.is_none() {
+            return Err(TracingS3Error::invalid_config(
+                "service",
+                "Key template uses {service} but no service name is configured",
+            ));
+        }
+        key_template.check_rotation(rotation)
+    }
+
+    /// Checks that the record separator is not empty.
+    pub(crate) fn check_record_separator(record_separator: &str) -> Result<(), TracingS3Error> {
+        if record_separator.is_empty() {
+            return Err(TracingS3Error::invalid_config(
+                "record_separator",
+                "Record separator must not be empty",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Opens the disk spool in `dir`, recovering the segments left by a previous run.
+    pub(crate) fn open_spool(
+        dir: impl AsRef<Path>,
+        size_limit_mb: SpoolSizeLimitMb,
+    ) -> Result<Arc<Mutex<Spool>>, TracingS3Error> {
+        let spool = Spool::open(dir, size_limit_mb.inner() * 1_024 * 1_024)
+            .map_err(|err| TracingS3Error::invalid_config("spool", err))?;
+        Ok(Arc::new(Mutex::new(spool)))
+    }
+
+    /// Resolves static credentials from the builder or the `S3_TRACING_AWS_*` variables,
+    /// falling back to the default provider chain when no access keys are configured.
+    fn credentials_from_keys(
+        access_key_id: Option<String>,
+        secret_access_key: Option<String>,
+        session_token: Option<String>,
+    ) -> Result<CredentialsSource, TracingS3Error> {
+        let access_key_id = access_key_id.or_else(|| env::var("S3_TRACING_AWS_ACCESS_KEY_ID").ok());
+        let secret_access_key =
+            secret_access_key.or_else(|| env::var("S3_TRACING_AWS_SECRET_ACCESS_KEY").ok());
+        let session_token = session_token.or_else(|| env::var("S3_TRACING_AWS_SESSION_TOKEN").ok());
+        CredentialsSource::from_optional_keys(access_key_id, secret_access_key, session_token)
+    }
+
+    /// Returns the configured value, or the environment variable if it is not set.
+    fn setting_or_env(
+        value: Option<String>,
+        field: &'static str,
+        env_var: &'static str,
+    ) -> Result<String, TracingS3Error> {
+        match value {
+            Some(value) => Ok(value),
+            None => env::var(env_var).map_err(|_| TracingS3Error::MissingConfig { field, env_var }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::key_template::HIVE_KEY_TEMPLATE;
+    use aws_config::BehaviorVersion;
+    use aws_credential_types::Credentials;
+    use aws_credential_types::provider::SharedCredentialsProvider;
+
+    fn builder() -> TracingS3ConfigBuilder {
+        TracingS3Config::builder()
+            .bucket("test-bucket")
+            .access_key_id("test")
+            .secret_access_key("test")
+    }
+
+    #[tokio::test]
+    async fn build_applies_defaults() {
+        let config = builder().build().await.unwrap();
+        assert_eq!(config.bucket, "test-bucket");
+        assert_eq!(config.prefix, DEFAULT_PREFIX);
+        assert_eq!(config.postfix, DEFAULT_POSTFIX);
+        assert_eq!(config.object_size_limit_mb, DEFAULT_OBJECT_SIZE_LIMIT_MB);
+        assert_eq!(config.cron_interval_in_ms, DEFAULT_CRON_INTERVAL_IN_MS);
+        assert_eq!(config.buffer_size_limit_kb, DEFAULT_BUFFER_SIZE_LIMIT_KB);
+        assert_eq!(
+            config.shutdown_timeout_in_ms,
+            DEFAULT_SHUTDOWN_TIMEOUT_IN_MS
+        );
+        assert_eq!(config.channel_capacity, None);
+        assert_eq!(config.rotation, Rotation::Daily);
+        assert_eq!(config.record_separator, "\n");
+        assert_eq!(config.denied_targets.len(), DEFAULT_DENIED_TARGETS.len());
+    }
+
+    #[tokio::test]
+    async fn rotation_defaults_to_the_finest_date_placeholder() {
+        let config = builder()
+            .key_template(HIVE_KEY_TEMPLATE)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(config.rotation, Rotation::Hourly);
+        let config = builder()
+            .key_template("{hour}/{minute}/{part}")
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(config.rotation, Rotation::Minutely);
+        let config = builder()
+            .key_template(HIVE_KEY_TEMPLATE)
+            .rotation(Rotation::Minutely)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(config.rotation, Rotation::Minutely);
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn config_mutators_share_the_builder_rules() {
+        let config = builder().build().await.unwrap();
+        let err = config.with_record_separator("").unwrap_err();
+        assert_eq!(err.field(), Some("record_separator"), "{err}");
+        let config = builder().build().await.unwrap();
+        let err = config.with_key_template(KeyTemplate::hive()).unwrap_err();
+        assert_eq!(err.field(), Some("key_template"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn debug_output_redacts_secrets() {
+        let builder = builder()
+            .secret_access_key("top-secret")
+            .session_token("session-token");
+        let debug = format!("{builder:?}");
+        assert!(!debug.contains("top-secret") && !debug.contains("session-token"));
+        assert!(debug.contains("<redacted>"), "{debug}");
+        let debug = format!(
+            "{:?}",
+            builder
+                .credentials(CredentialsSource::Static {
+                    access_key_id: "test".to_string(),
+                    secret_access_key: "top-secret".to_string(),
+                    session_token: Some("session-token".to_string()),
+                })
+                .build()
+                .await
+                .unwrap()
+        );
+        assert!(!debug.contains("top-secret") && !debug.contains("session-token"));
+    }
+
+    #[test]
+    fn build_blocking_works_without_a_runtime() {
+        let config = builder()
+            .worker_runtime(WorkerRuntime::DedicatedThread)
+            .build_blocking()
+            .unwrap();
+        assert_eq!(config.bucket, "test-bucket");
+        assert!(matches!(config.worker_runtime, WorkerRuntime::Thread(_)));
+        let err = builder().build_blocking().unwrap_err();
+        assert_eq!(err.field(), Some("worker_runtime"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn build_uses_the_shared_sdk_config() {
+        let sdk_config = SdkConfig::builder()
+            .region(Region::new("eu-central-1"))
+            .credentials_provider(SharedCredentialsProvider::new(Credentials::new(
+                "test", "test", None, None, "test",
+            )))
+            .behavior_version(BehaviorVersion::latest())
+            .build();
+        let config = TracingS3Config::builder()
+            .bucket("test-bucket")
+            .sdk_config(&sdk_config)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(
+            config.aws_client.config().region(),
+            Some(&Region::new("eu-central-1"))
+        );
+    }
+
+    #[tokio::test]
+    async fn build_names_the_invalid_field() {
+        let cases = [
+            (builder().object_size_limit_mb(0), "object_size_limit_mb"),
+            (
+                builder().object_size_limit_mb(50_001),
+                "object_size_limit_mb",
+            ),
+            (builder().cron_interval_in_ms(0), "cron_interval_in_ms"),
+            (builder().buffer_size_limit_kb(0), "buffer_size_limit_kb"),
+            (builder().channel_capacity(0), "channel_capacity"),
+            (builder().spool("unused", 0), "spool"),
+            (
+                builder().rotation(Rotation::Custom(std::time::Duration::ZERO)),
+                "rotation",
+            ),
+            (builder().key_template("{date}/{prefix}"), "key_template"),
+            (builder().key_template("{service}/{part}"), "service"),
+            (
+                builder()
+                    .key_template(HIVE_KEY_TEMPLATE)
+                    .rotation(Rotation::Daily),
+                "key_template",
+            ),
+            (
+                builder()
+                    .key_template("{date}/{part}")
+                    .rotation(Rotation::Custom(std::time::Duration::from_secs(
+                        7 * 60 * 60,
+                    ))),
+                "key_template",
+            ),
+            (builder().record_separator(""), "record_separator"),
+            (
+                TracingS3Config::builder()
+                    .bucket("test-bucket")
+                    .access_key_id("test"),
+                "secret_access_key",
+            ),
+        ];
+        for (builder, field) in cases {
+            let err = builder.build().await.unwrap_err();
+            assert_eq!(err.field(), Some(field), "{err}");
+            assert!(err.to_string().contains(field));
+        }
+    }
+}
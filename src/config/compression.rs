@@ -0,0 +1,99 @@
+use crate::error::Result;
+use flate2::write::GzEncoder;
+use std::io::Write;
+
+/// Compression applied to uploaded log data.
+///
+/// Every upload is compressed on its own into a complete gzip member or zstd frame. Both
+/// formats allow concatenating members/frames, so an object built from many appends still
+/// decompresses to the full log with standard tools (`gzip -d`, `zstd -d`) and libraries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Compression {
+    /// Uploads plain NDJSON. The default.
+    #[default]
+    None,
+    /// Gzip (RFC 1952), `.gz` extension, `Content-Encoding: gzip`.
+    Gzip,
+    /// Zstandard, `.zst` extension, `Content-Encoding: zstd`.
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the extension appended to the configured postfix, e.g. `.gz`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Returns the `Content-Encoding` set on created objects, if any.
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Compresses data into a single self-contained gzip member or zstd frame.
+    ///
+    /// # Arguments
+    /// * `data` - The data to compress
+    ///
+    /// # Returns
+    /// * `Ok(Vec<u8>)` - The compressed data, or a copy of `data` without compression
+    /// * `Err(TracingS3Error)` - If the encoder fails
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zstd => Ok(zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::compression::Compression;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn concatenated_gzip_members_decompress_to_all_data() {
+        let mut object = Compression::Gzip.compress(b"first\n").unwrap();
+        object.extend(Compression::Gzip.compress(b"second\n").unwrap());
+        let mut decoded = String::new();
+        MultiGzDecoder::new(object.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "first\nsecond\n");
+    }
+
+    #[test]
+    fn concatenated_zstd_frames_decompress_to_all_data() {
+        let mut object = Compression::Zstd.compress(b"first\n").unwrap();
+        object.extend(Compression::Zstd.compress(b"second\n").unwrap());
+        assert_eq!(
+            zstd::decode_all(object.as_slice()).unwrap(),
+            b"first\nsecond\n"
+        );
+    }
+
+    #[test]
+    fn none_keeps_data_as_is() {
+        assert_eq!(Compression::None.compress(b"plain").unwrap(), b"plain");
+        assert_eq!(Compression::None.extension(), "");
+        assert_eq!(Compression::None.content_encoding(), None);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::error::TracingS3Error;
+use aws_config::default_provider::credentials::DefaultCredentialsChain;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_credential_types::Credentials;
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_types::region::Region;
+use std::fmt;
+
+/// Printed by `Debug` in place of secrets.
+pub(crate) const REDACTED: &str = "<redacted>";
+
+/// A secret string whose `Debug` output is [`REDACTED`].
+#[derive(Clone, Default, PartialEq, Eq)]
+pub(crate) struct Secret(pub(crate) String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Where the layer gets its AWS credentials from.
+///
+/// Providers other than [`CredentialsSource::Static`] are cached by the S3 client and
+/// refreshed shortly before they expire, so they stay valid over a long-lived process.
+///
+/// `Debug` output redacts the secret access key, the session token and the external ID.
+#[derive(Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(tag = "type", rename_all = "snake_case")
+)]
+pub enum CredentialsSource {
+    /// Fixed credentials, optionally with the session token of temporary credentials.
+    Static {
+        access_key_id: String,
+        secret_access_key: String,
+        session_token: Option<String>,
+    },
+    /// The standard AWS provider chain: environment variables, shared profile, web identity
+    /// (e.g. EKS IRSA), ECS container credentials and EC2 instance metadata.
+    #[default]
+    DefaultChain,
+    /// Assumes `role_arn` with STS, using the default chain as the source credentials.
+    AssumeRole {
+        role_arn: String,
+        external_id: Option<String>,
+        session_name: Option<String>,
+    },
+}
+
+impl fmt::Debug for CredentialsSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialsSource::Static {
+                access_key_id,
+                secret_access_key: _,
+                session_token,
+            } => f
+                .debug_struct("Static")
+                .field("access_key_id", access_key_id)
+                .field("secret_access_key", &REDACTED)
+                .field("session_token", &session_token.as_ref().map(|_| REDACTED))
+                .finish(),
+            CredentialsSource::DefaultChain => f.write_str("DefaultChain"),
+            CredentialsSource::AssumeRole {
+                role_arn,
+                external_id,
+                session_name,
+            } => f
+                .debug_struct("AssumeRole")
+                .field("role_arn", role_arn)
+                .field("external_id", &external_id.as_ref().map(|_| REDACTED))
+                .field("session_name", session_name)
+                .finish(),
+        }
+    }
+}
+
+impl CredentialsSource {
+    /// Creates a static credentials source.
+    ///
+    /// # Arguments
+    /// * `access_key_id` - The AWS access key ID
+    /// * `secret_access_key` - The AWS secret access key
+    pub fn from_keys(access_key_id: &str, secret_access_key: &str) -> Self {
+        CredentialsSource::Static {
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            session_token: None,
+        }
+    }
+
+    /// Creates an assume-role credentials source.
+    ///
+    /// # Arguments
+    /// * `role_arn` - The ARN of the role to assume
+    /// * `external_id` - The external ID required by the role's trust policy, if any
+    pub fn assume_role(role_arn: &str, external_id: Option<&str>) -> Self {
+        CredentialsSource::AssumeRole {
+            role_arn: role_arn.to_string(),
+            external_id: external_id.map(str::to_string),
+            session_name: None,
+        }
+    }
+
+    /// Creates the source for optional access keys: static credentials if both keys are set,
+    /// the default chain if neither is.
+    ///
+    /// # Arguments
+    /// * `access_key_id` - The AWS access key ID, if configured
+    /// * `secret_access_key` - The AWS secret access key, if configured
+    /// * `session_token` - The session token of temporary credentials, if configured
+    ///
+    /// # Returns
+    /// * `Ok(CredentialsSource)` - The source to use
+    /// * `Err(TracingS3Error)` - Naming the missing key if only one of them is set
+    pub(crate) fn from_optional_keys(
+        access_key_id: Option<String>,
+        secret_access_key: Option<String>,
+        session_token: Option<String>,
+    ) -> Result<Self, TracingS3Error> {
+        match (access_key_id, secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(CredentialsSource::Static {
+                access_key_id,
+                secret_access_key,
+                session_token,
+            }),
+            (Some(_), None) => Err(TracingS3Error::MissingConfig {
+                field: "secret_access_key",
+                env_var: "S3_TRACING_AWS_SECRET_ACCESS_KEY",
+            }),
+            (None, Some(_)) => Err(TracingS3Error::MissingConfig {
+                field: "access_key_id",
+                env_var: "S3_TRACING_AWS_ACCESS_KEY_ID",
+            }),
+            (None, None) => Ok(CredentialsSource::DefaultChain),
+        }
+    }
+
+    /// Returns whether the source uses fixed credentials.
+    pub fn is_static(&self) -> bool {
+        matches!(self, CredentialsSource::Static { .. })
+    }
+
+    /// Creates the credentials provider for this source.
+    ///
+    /// # Arguments
+    /// * `region` - The region used to reach STS and the instance metadata service
+    ///
+    /// # Returns
+    /// A provider the S3 client caches and refreshes as needed
+    pub async fn provider(&self, region: Region) -> SharedCredentialsProvider {
+        match self {
+            CredentialsSource::Static {
+                access_key_id,
+                secret_access_key,
+                session_token,
+            } => SharedCredentialsProvider::new(Credentials::new(
+                access_key_id,
+                secret_access_key,
+                session_token.clone(),
+                None,
+                "AWS",
+            )),
+            CredentialsSource::DefaultChain => SharedCredentialsProvider::new(
+                DefaultCredentialsChain::builder()
+                    .region(region)
+                    .build()
+                    .await,
+            ),
+            CredentialsSource::AssumeRole {
+                role_arn,
+                external_id,
+                session_name,
+            } => {
+                let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+                    .region(region.clone())
+                    .load()
+                    .await;
+                SharedCredentialsProvider::new(
+                    Self::assume_role_provider(
+                        role_arn,
+                        external_id.as_deref(),
+                        session_name.as_deref(),
+                        region,
+                        &sdk_config,
+                    )
+                    .await,
+                )
+            }
+        }
+    }
+
+    /// Creates the provider assuming `role_arn`, with the source credentials and STS
+    /// settings of `sdk_config`.
+    async fn assume_role_provider(
+        role_arn: &str,
+        external_id: Option<&str>,
+        session_name: Option<&str>,
+        region: Region,
+        sdk_config: &SdkConfig,
+    ) -> AssumeRoleProvider {
+        let mut builder = AssumeRoleProvider::builder(role_arn)
+            .region(region)
+            .configure(sdk_config);
+        if let Some(external_id) = external_id {
+            builder = builder.external_id(external_id);
+        }
+        if let Some(session_name) = session_name {
+            builder = builder.session_name(session_name);
+        }
+        builder.build().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fake_s3::FakeS3;
+    use aws_credential_types::provider::ProvideCredentials;
+
+    #[test]
+    fn missing_keys_fall_back_to_the_default_chain() {
+        assert_eq!(
+            CredentialsSource::from_optional_keys(None, None, None).unwrap(),
+            CredentialsSource::DefaultChain
+        );
+        assert_eq!(
+            CredentialsSource::from_optional_keys(
+                Some("key".to_string()),
+                Some("secret".to_string()),
+                None
+            )
+            .unwrap(),
+            CredentialsSource::from_keys("key", "secret")
+        );
+        let err =
+            CredentialsSource::from_optional_keys(Some("key".to_string()), None, None).unwrap_err();
+        assert_eq!(err.field(), Some("secret_access_key"));
+    }
+
+    #[tokio::test]
+    async fn assume_role_forwards_the_external_id_and_session_name() {
+        let sts = FakeS3::start().await;
+        sts.fail_next(403, "AccessDenied");
+        let region = Region::new("us-west-2");
+        let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+            .region(region.clone())
+            .endpoint_url(sts.endpoint())
+            .credentials_provider(Credentials::new("key", "secret", None, None, "test"))
+            .load()
+            .await;
+        let provider = CredentialsSource::assume_role_provider(
+            "arn:aws:iam::123456789012:role/logs",
+            Some("tenant-7"),
+            Some("tracing-s3"),
+            region,
+            &sdk_config,
+        )
+        .await;
+        assert!(provider.provide_credentials().await.is_err());
+
+        let requests = sts.requests();
+        let body = String::from_utf8_lossy(&requests[0].body);
+        assert!(body.contains("Action=AssumeRole"), "{body}");
+        assert!(
+            body.contains("RoleArn=arn%3Aaws%3Aiam%3A%3A123456789012%3Arole%2Flogs"),
+            "{body}"
+        );
+        assert!(body.contains("ExternalId=tenant-7"), "{body}");
+        assert!(body.contains("RoleSessionName=tracing-s3"), "{body}");
+    }
+
+    #[test]
+    fn debug_output_redacts_secrets() {
+        let source = CredentialsSource::Static {
+            access_key_id: "key".to_string(),
+            secret_access_key: "top-secret".to_string(),
+            session_token: Some("session-token".to_string()),
+        };
+        let debug = format!("{source:?}");
+        assert!(debug.contains("key") && debug.contains(REDACTED), "{debug}");
+        assert!(!debug.contains("top-secret") && !debug.contains("session-token"));
+        let debug = format!(
+            "{:?}",
+            CredentialsSource::assume_role("arn:aws:iam::123456789012:role/logs", Some("tenant-7"))
+        );
+        assert!(
+            debug.contains("role/logs") && !debug.contains("tenant-7"),
+            "{debug}"
+        );
+    }
+
+    #[tokio::test]
+    async fn static_source_keeps_the_session_token() {
+        let source = CredentialsSource::Static {
+            access_key_id: "key".to_string(),
+            secret_access_key: "secret".to_string(),
+            session_token: Some("token".to_string()),
+        };
+        let credentials = source
+            .provider(Region::new("us-west-2"))
+            .await
+            .provide_credentials()
+            .await
+            .unwrap();
+        assert_eq!(credentials.access_key_id(), "key");
+        assert_eq!(credentials.secret_access_key(), "secret");
+        assert_eq!(credentials.session_token(), Some("token"));
+    }
+}
@@ -0,0 +1,445 @@
+use crate::config::builder::TracingS3ConfigBuilder;
+use crate::config::compression::Compression;
+use crate::config::credentials_source::CredentialsSource;
+use crate::config::key_template::HIVE_KEY_TEMPLATE;
+use crate::config::retry_policy::RetryPolicy;
+use crate::config::rotation::Rotation;
+use crate::config::tracing_s3_config::TracingS3Config;
+use crate::config::worker_runtime::WorkerRuntime;
+use crate::error::TracingS3Error;
+use crate::layer::event_queue::OverflowPolicy;
+use crate::sink::s3_sink::UploadStrategy;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::Level;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+/// Key template preset name that selects [`HIVE_KEY_TEMPLATE`].
+pub const HIVE_PRESET: &str = "hive";
+
+/// Configuration of the S3 tracing layer as read from a file (TOML, YAML, JSON or any other
+/// serde format). Available with the `serde` feature.
+///
+/// Every field is optional and has the same default as the matching
+/// [`TracingS3ConfigBuilder`] setter. Values are validated with the same rules when the
+/// configuration is built. Unknown fields are rejected. `Debug` output redacts the secrets
+/// of `credentials`.
+///
+/// ```toml
+/// bucket = "logs--usw2-az1--x-s3"
+/// prefix = "api"
+/// key_template = "hive"
+/// rotation = "hourly"
+/// compression = "zstd"
+/// cron_interval_in_ms = 1000
+///
+/// [credentials]
+/// type = "assume_role"
+/// role_arn = "arn:aws:iam::123456789012:role/log-writer"
+/// external_id = "external-id"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub region: Option<String>,
+    pub bucket: Option<String>,
+    pub prefix: Option<String>,
+    pub postfix: Option<String>,
+    pub endpoint: Option<String>,
+    pub credentials: Option<CredentialsSource>,
+    /// A key template, or `"hive"` for [`HIVE_KEY_TEMPLATE`], which rotates hourly unless
+    /// `rotation` is set.
+    pub key_template: Option<String>,
+    pub service: Option<String>,
+    pub object_size_limit_mb: Option<u64>,
+    pub cron_interval_in_ms: Option<u64>,
+    pub buffer_size_limit_kb: Option<u64>,
+    pub shutdown_timeout_in_ms: Option<u64>,
+    pub retry: Option<RetryConfig>,
+    pub spool: Option<SpoolConfig>,
+    pub channel_capacity: Option<usize>,
+    pub overflow_policy: Option<OverflowPolicy>,
+    pub upload_strategy: Option<UploadStrategy>,
+    pub rotation: Option<RotationConfig>,
+    /// An IANA timezone name, e.g. `"Europe/Berlin"`.
+    pub timezone: Option<String>,
+    /// The layout of the uploaded records, see [`FormatConfig`].
+    pub format: Option<FormatConfig>,
+    pub compression: Option<Compression>,
+    /// The string terminating every record.
+    pub record_separator: Option<String>,
+    pub denied_targets: Option<Vec<String>>,
+    /// Whether to add the current span to every record.
+    pub current_span: Option<bool>,
+    /// Whether to add the span stack to every record.
+    pub span_list: Option<bool>,
+    /// The recorded span lifecycle events, e.g. `["new", "close"]` or `["full"]`.
+    pub span_events: Option<Vec<SpanEventConfig>>,
+    /// Whether to add `trace_id` and `span_id` to every record.
+    pub trace_context: Option<bool>,
+    /// The least severe level that is uploaded right away, e.g. `"error"`.
+    pub flush_on_level: Option<String>,
+    pub flush_debounce_in_ms: Option<u64>,
+    /// Where the background worker runs, `"ambient"` or `"dedicated_thread"`.
+    pub worker_runtime: Option<WorkerRuntimeConfig>,
+}
+
+/// Retry settings of a [`FileConfig`], see [`RetryPolicy::new`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    #[serde(default = "default_jitter")]
+    pub jitter: bool,
+}
+
+/// Disk spool settings of a [`FileConfig`], see [`TracingS3ConfigBuilder::spool`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpoolConfig {
+    pub dir: PathBuf,
+    pub size_limit_mb: u64,
+}
+
+/// Rotation window of a [`FileConfig`]: `"minutely"`, `"hourly"`, `"daily"` or
+/// `{ custom = { window_ms = 600000 } }`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationConfig {
+    Minutely,
+    Hourly,
+    Daily,
+    Custom { window_ms: u64 },
+}
+
+/// Record layout of a [`FileConfig`]: `"ndjson"` or `"json"`. Records are always JSON
+/// objects, one per event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FormatConfig {
+    /// JSON records terminated by a newline, the default. Cannot be combined with another
+    /// `record_separator`.
+    Ndjson,
+    /// JSON records terminated by `record_separator`, a newline unless set.
+    Json,
+}
+
+/// A span lifecycle event of a [`FileConfig`], see [`TracingS3ConfigBuilder::span_events`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpanEventConfig {
+    None,
+    New,
+    Enter,
+    Exit,
+    Close,
+    Active,
+    Full,
+}
+
+impl From<SpanEventConfig> for FmtSpan {
+    fn from(span_event: SpanEventConfig) -> Self {
+        match span_event {
+            SpanEventConfig::None => FmtSpan::NONE,
+            SpanEventConfig::New => FmtSpan::NEW,
+            SpanEventConfig::Enter => FmtSpan::ENTER,
+            SpanEventConfig::Exit => FmtSpan::EXIT,
+            SpanEventConfig::Close => FmtSpan::CLOSE,
+            SpanEventConfig::Active => FmtSpan::ACTIVE,
+            SpanEventConfig::Full => FmtSpan::FULL,
+        }
+    }
+}
+
+/// Where the background worker of a [`FileConfig`] runs: `"ambient"` or `"dedicated_thread"`,
+/// see [`WorkerRuntime`]. A runtime handle can only be set in code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerRuntimeConfig {
+    Ambient,
+    DedicatedThread,
+}
+
+impl From<WorkerRuntimeConfig> for WorkerRuntime {
+    fn from(worker_runtime: WorkerRuntimeConfig) -> Self {
+        match worker_runtime {
+            WorkerRuntimeConfig::Ambient => WorkerRuntime::Ambient,
+            WorkerRuntimeConfig::DedicatedThread => WorkerRuntime::DedicatedThread,
+        }
+    }
+}
+
+fn default_jitter() -> bool {
+    true
+}
+
+impl FileConfig {
+    /// Converts the file configuration into a builder, so settings that cannot come from a
+    /// file (such as a shared `SdkConfig`) can still be added in code.
+    ///
+    /// # Returns
+    /// * `Ok(TracingS3ConfigBuilder)` - If the retry settings, timezone and level are valid
+    /// * `Err(TracingS3Error)` - Naming the first setting that is invalid
+    pub fn into_builder(self) -> Result<TracingS3ConfigBuilder, TracingS3Error> {
+        let mut builder = TracingS3Config::builder();
+        if let Some(region) = self.region {
+            builder = builder.region(&region);
+        }
+        if let Some(bucket) = self.bucket {
+            builder = builder.bucket(&bucket);
+        }
+        if let Some(prefix) = self.prefix {
+            builder = builder.prefix(&prefix);
+        }
+        if let Some(postfix) = self.postfix {
+            builder = builder.postfix(&postfix);
+        }
+        if let Some(endpoint) = self.endpoint {
+            builder = builder.endpoint(&endpoint);
+        }
+        if let Some(credentials) = self.credentials {
+            builder = builder.credentials(credentials);
+        }
+        if let Some(key_template) = self.key_template {
+            builder = match key_template.as_str() {
+                HIVE_PRESET => builder.key_template(HIVE_KEY_TEMPLATE),
+                key_template => builder.key_template(key_template),
+            };
+        }
+        if let Some(service) = self.service {
+            builder = builder.service(&service);
+        }
+        if let Some(object_size_limit_mb) = self.object_size_limit_mb {
+            builder = builder.object_size_limit_mb(object_size_limit_mb);
+        }
+        if let Some(cron_interval_in_ms) = self.cron_interval_in_ms {
+            builder = builder.cron_interval_in_ms(cron_interval_in_ms);
+        }
+        if let Some(buffer_size_limit_kb) = self.buffer_size_limit_kb {
+            builder = builder.buffer_size_limit_kb(buffer_size_limit_kb);
+        }
+        if let Some(shutdown_timeout_in_ms) = self.shutdown_timeout_in_ms {
+            builder = builder.shutdown_timeout_in_ms(shutdown_timeout_in_ms);
+        }
+        if let Some(retry) = self.retry {
+            let retry_policy = RetryPolicy::new(
+                retry.max_attempts,
+                retry.base_delay_ms,
+                retry.max_delay_ms,
+                retry.jitter,
+            )?;
+            builder = builder.retry_policy(retry_policy);
+        }
+        if let Some(spool) = self.spool {
+            builder = builder.spool(spool.dir, spool.size_limit_mb);
+        }
+        if let Some(channel_capacity) = self.channel_capacity {
+            builder = builder.channel_capacity(channel_capacity);
+        }
+        if let Some(overflow_policy) = self.overflow_policy {
+            builder = builder.overflow_policy(overflow_policy);
+        }
+        if let Some(upload_strategy) = self.upload_strategy {
+            builder = builder.upload_strategy(upload_strategy);
+        }
+        if let Some(rotation) = self.rotation {
+            builder = builder.rotation(match rotation {
+                RotationConfig::Minutely => Rotation::Minutely,
+                RotationConfig::Hourly => Rotation::Hourly,
+                RotationConfig::Daily => Rotation::Daily,
+                RotationConfig::Custom { window_ms } => {
+                    Rotation::Custom(Duration::from_millis(window_ms))
+                }
+            });
+        }
+        if let Some(timezone) = self.timezone {
+            let timezone = timezone
+                .parse::<Tz>()
+                .map_err(|err| TracingS3Error::invalid_config("timezone", err))?;
+            builder = builder.timezone(timezone);
+        }
+        if let Some(compression) = self.compression {
+            builder = builder.compression(compression);
+        }
+        match (self.format, self.record_separator) {
+            (Some(FormatConfig::Ndjson), Some(record_separator)) if record_separator != "\n" => {
+                return Err(TracingS3Error::invalid_config(
+                    "format",
+                    "The ndjson format terminates records with a newline; use the json format \
+                     for another record separator",
+                ));
+            }
+            (_, Some(record_separator)) => {
+                builder = builder.record_separator(&record_separator);
+            }
+            (_, None) => {}
+        }
+        if let Some(denied_targets) = self.denied_targets {
+            builder = builder.denied_targets(denied_targets);
+        }
+        if let Some(current_span) = self.current_span {
+            builder = builder.current_span(current_span);
+        }
+        if let Some(span_list) = self.span_list {
+            builder = builder.span_list(span_list);
+        }
+        if let Some(trace_context) = self.trace_context {
+            builder = builder.trace_context(trace_context);
+        }
+        if let Some(flush_on_level) = self.flush_on_level {
+            let level = flush_on_level
+                .parse::<Level>()
+                .map_err(|err| TracingS3Error::invalid_config("flush_on_level", err))?;
+            builder = builder.flush_on_level(level);
+        }
+        if let Some(flush_debounce_in_ms) = self.flush_debounce_in_ms {
+            builder = builder.flush_debounce_in_ms(flush_debounce_in_ms);
+        }
+        if let Some(worker_runtime) = self.worker_runtime {
+            builder = builder.worker_runtime(worker_runtime.into());
+        }
+        if let Some(span_events) = self.span_events {
+            builder = builder.span_events(
+                span_events
+                    .into_iter()
+                    .map(FmtSpan::from)
+                    .fold(FmtSpan::NONE, |all, span_event| all | span_event),
+            );
+        }
+        Ok(builder)
+    }
+
+    /// Validates the file configuration and creates the live configuration.
+    ///
+    /// # Returns
+    /// * `Ok(TracingS3Config)` - If every setting is valid
+    /// * `Err(TracingS3Error)` - Naming the first setting that is missing or invalid
+    pub async fn build(self) -> Result<TracingS3Config, TracingS3Error> {
+        self.into_builder()?.build().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn toml_config_builds_a_live_config() {
+        let file_config: FileConfig = toml::from_str(
+            r#"
+            bucket = "logs"
+            prefix = "api"
+            key_template = "hive"
+            rotation = "hourly"
+            timezone = "Europe/Berlin"
+            compression = "zstd"
+            format = "ndjson"
+            upload_strategy = "rolling_put"
+            overflow_policy = { sample = 10 }
+            channel_capacity = 1000
+            cron_interval_in_ms = 1000
+            span_events = ["new", "close"]
+            flush_on_level = "warn"
+            worker_runtime = "dedicated_thread"
+
+            [credentials]
+            type = "static"
+            access_key_id = "key"
+            secret_access_key = "secret"
+            "#,
+        )
+        .unwrap();
+        let config = file_config.build().await.unwrap();
+        assert_eq!(config.bucket, "logs");
+        assert_eq!(config.prefix, "api");
+        assert_eq!(config.rotation, Rotation::Hourly);
+        assert_eq!(config.timezone, chrono_tz::Europe::Berlin);
+        assert_eq!(config.compression, Compression::Zstd);
+        assert_eq!(config.upload_strategy, UploadStrategy::RollingPut);
+        assert_eq!(config.overflow_policy, OverflowPolicy::Sample(10));
+        assert_eq!(config.channel_capacity, Some(1000));
+        assert_eq!(config.cron_interval_in_ms, 1000);
+        assert_eq!(config.span_events, FmtSpan::NEW | FmtSpan::CLOSE);
+        assert_eq!(config.flush_on_level, Some(Level::WARN));
+        assert!(matches!(
+            config.worker_runtime,
+            WorkerRuntime::DedicatedThread
+        ));
+    }
+
+    #[tokio::test]
+    async fn hive_preset_rotates_hourly_by_default() {
+        let file_config: FileConfig = toml::from_str(
+            r#"
+            bucket = "logs"
+            key_template = "hive"
+            credentials = { type = "static", access_key_id = "key", secret_access_key = "secret" }
+            "#,
+        )
+        .unwrap();
+        let config = file_config.clone().build().await.unwrap();
+        assert_eq!(config.rotation, Rotation::Hourly);
+
+        let file_config = FileConfig {
+            rotation: Some(RotationConfig::Daily),
+            ..file_config
+        };
+        let err = file_config.build().await.unwrap_err();
+        assert_eq!(err.field(), Some("key_template"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn format_maps_to_the_record_separator() {
+        let credentials = r#"credentials = { type = "static", access_key_id = "key", secret_access_key = "secret" }"#;
+        let build = |extra: &str| {
+            let file_config: FileConfig =
+                toml::from_str(&format!("bucket = \"logs\"\n{credentials}\n{extra}")).unwrap();
+            file_config.build()
+        };
+        let config = build(r#"format = "ndjson""#).await.unwrap();
+        assert_eq!(config.record_separator, "\n");
+        let config = build("format = \"json\"\nrecord_separator = \"\\u0000\"")
+            .await
+            .unwrap();
+        assert_eq!(config.record_separator, "\0");
+        let err = build("format = \"ndjson\"\nrecord_separator = \"\\r\\n\"")
+            .await
+            .unwrap_err();
+        assert_eq!(err.field(), Some("format"), "{err}");
+    }
+
+    #[test]
+    fn debug_output_redacts_credentials() {
+        let file_config: FileConfig = toml::from_str(
+            r#"credentials = { type = "static", access_key_id = "key", secret_access_key = "top-secret" }"#,
+        )
+        .unwrap();
+        let debug = format!("{file_config:?}");
+        assert!(!debug.contains("top-secret"), "{debug}");
+    }
+
+    #[tokio::test]
+    async fn file_config_is_validated_like_the_builder() {
+        let file_config: FileConfig = serde_json::from_str(
+            r#"{"bucket": "logs", "credentials": {"type": "default_chain"}, "buffer_size_limit_kb": 0}"#,
+        )
+        .unwrap();
+        let err = file_config.build().await.unwrap_err();
+        assert_eq!(err.field(), Some("buffer_size_limit_kb"));
+
+        let file_config = FileConfig {
+            timezone: Some("Mars/Olympus_Mons".to_string()),
+            ..FileConfig::default()
+        };
+        assert_eq!(
+            file_config.into_builder().unwrap_err().field(),
+            Some("timezone")
+        );
+        assert!(serde_json::from_str::<FileConfig>(r#"{"bukket": "logs"}"#).is_err());
+        assert!(toml::from_str::<FileConfig>(r#"format = "text""#).is_err());
+    }
+}
@@ -0,0 +1,396 @@
+use crate::config::rotation::Rotation;
+use crate::error::{Result, TracingS3Error};
+use chrono::NaiveDateTime;
+use std::time::Duration;
+use tracing::Level;
+
+/// The key layout used when none is configured: `{date}/{part}/{prefix}-{nonce}.{postfix}`.
+pub const DEFAULT_KEY_TEMPLATE: &str = "{date}/{part}/{prefix}-{nonce}.{postfix}";
+
+/// Hive-style partitioned key layout, e.g. `app/year=2024/month=01/day=01/hour=05/...`,
+/// letting Athena, Glue or DuckDB prune partitions. Objects rotate hourly with this layout
+/// unless a rotation is configured, so every object lands in the partition of the hour it
+/// covers.
+pub const HIVE_KEY_TEMPLATE: &str = "{prefix}/year={year}/month={month}/day={day}/hour={hour}/{hostname}-{pid}-{nonce}-{part}.{postfix}";
+
+/// A placeholder of a [`KeyTemplate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Prefix,
+    Postfix,
+    Date,
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Hostname,
+    Pid,
+    Service,
+    Nonce,
+    Part,
+    Level,
+}
+
+impl Placeholder {
+    const ALL: [Placeholder; 14] = [
+        Placeholder::Prefix,
+        Placeholder::Postfix,
+        Placeholder::Date,
+        Placeholder::Year,
+        Placeholder::Month,
+        Placeholder::Day,
+        Placeholder::Hour,
+        Placeholder::Minute,
+        Placeholder::Hostname,
+        Placeholder::Pid,
+        Placeholder::Service,
+        Placeholder::Nonce,
+        Placeholder::Part,
+        Placeholder::Level,
+    ];
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|placeholder| placeholder.name() == name)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Placeholder::Prefix => "prefix",
+            Placeholder::Postfix => "postfix",
+            Placeholder::Date => "date",
+            Placeholder::Year => "year",
+            Placeholder::Month => "month",
+            Placeholder::Day => "day",
+            Placeholder::Hour => "hour",
+            Placeholder::Minute => "minute",
+            Placeholder::Hostname => "hostname",
+            Placeholder::Pid => "pid",
+            Placeholder::Service => "service",
+            Placeholder::Nonce => "nonce",
+            Placeholder::Part => "part",
+            Placeholder::Level => "level",
+        }
+    }
+
+    /// Returns how long the rendered value stays the same, for date placeholders. Years and
+    /// months are made of whole days, so a window that divides a day fits them as well.
+    fn period(&self) -> Option<Duration> {
+        match self {
+            Placeholder::Minute => Some(Duration::from_secs(60)),
+            Placeholder::Hour => Some(Duration::from_secs(60 * 60)),
+            Placeholder::Date | Placeholder::Year | Placeholder::Month | Placeholder::Day => {
+                Some(Duration::from_secs(24 * 60 * 60))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// The values substituted into a [`KeyTemplate`].
+#[derive(Debug, Clone)]
+pub struct KeyContext<'a> {
+    pub prefix: &'a str,
+    pub postfix: &'a str,
+    pub nonce: &'a str,
+    pub part: u64,
+    /// Start of the current rotation window, used for the date placeholders.
+    pub window_start: NaiveDateTime,
+    pub hostname: &'a str,
+    pub pid: u32,
+    pub service: Option<&'a str>,
+    pub level: Level,
+}
+
+/// Object key layout with placeholders.
+///
+/// Supported placeholders: `{prefix}`, `{postfix}`, `{date}` (`YYYY-MM-DD`), `{year}`,
+/// `{month}`, `{day}`, `{hour}`, `{minute}`, `{hostname}`, `{pid}`, `{service}`, `{nonce}`,
+/// `{part}` and `{level}`. Dates are those of the start of the current rotation window, so
+/// the window must not span more than one value of any date placeholder, see
+/// [`KeyTemplate::check_rotation`]. `{{` and `}}` produce literal braces.
+///
+/// Templates must contain `{part}`, so rotated objects get distinct keys. With `{level}`,
+/// records of each level are written to their own objects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyTemplate {
+    segments: Vec<Segment>,
+}
+
+impl KeyTemplate {
+    /// Parses a key template.
+    ///
+    /// # Arguments
+    /// * `template` - The template, e.g. `logs/{year}/{month}/{day}/{prefix}-{part}.{postfix}`
+    ///
+    /// # Returns
+    /// * `Ok(KeyTemplate)` - If the template is valid
+    /// * `Err(TracingS3Error)` - If a placeholder is unknown or unterminated, a brace is
+    ///   unmatched, `{part}` is missing or the key would start with `/`
+    pub fn new(template: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => {
+                                return Err(TracingS3Error::invalid_config(
+                                    "key_template",
+                                    format!(
+                                        "Unterminated placeholder `{{{name}` in key template `{template}`"
+                                    ),
+                                ));
+                            }
+                        }
+                    }
+                    let placeholder = Placeholder::parse(&name).ok_or_else(|| {
+                        TracingS3Error::invalid_config(
+                            "key_template",
+                            format!(
+                                "Unknown placeholder `{{{name}}}` in key template `{template}`"
+                            ),
+                        )
+                    })?;
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(placeholder));
+                }
+                '}' => {
+                    return Err(TracingS3Error::invalid_config(
+                        "key_template",
+                        format!("Unmatched `}}` in key template `{template}`"),
+                    ));
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        let key_template = Self { segments };
+        if !key_template.uses(Placeholder::Part) {
+            return Err(TracingS3Error::invalid_config(
+                "key_template",
+                format!(
+                    "Key template `{template}` must contain `{{part}}` so rotated objects get distinct keys"
+                ),
+            ));
+        }
+        if template.starts_with('/') {
+            return Err(TracingS3Error::invalid_config(
+                "key_template",
+                format!("Key template `{template}` must not start with `/`"),
+            ));
+        }
+        Ok(key_template)
+    }
+
+    /// Returns the Hive-style preset, see [`HIVE_KEY_TEMPLATE`].
+    pub fn hive() -> Self {
+        Self::new(HIVE_KEY_TEMPLATE).expect("Hive key template is valid")
+    }
+
+    fn uses(&self, placeholder: Placeholder) -> bool {
+        self.segments.contains(&Segment::Placeholder(placeholder))
+    }
+
+    /// Returns true if the template contains `{service}`.
+    pub fn uses_service(&self) -> bool {
+        self.uses(Placeholder::Service)
+    }
+
+    /// Returns true if the template contains `{level}`.
+    pub fn uses_level(&self) -> bool {
+        self.uses(Placeholder::Level)
+    }
+
+    /// Returns the longest rotation window that fits the date placeholders of the template,
+    /// used when no rotation is configured: [`Rotation::Minutely`] with `{minute}`,
+    /// [`Rotation::Hourly`] with `{hour}` (e.g. [`HIVE_KEY_TEMPLATE`]) and
+    /// [`Rotation::Daily`] otherwise.
+    pub fn default_rotation(&self) -> Rotation {
+        if self.uses(Placeholder::Minute) {
+            Rotation::Minutely
+        } else if self.uses(Placeholder::Hour) {
+            Rotation::Hourly
+        } else {
+            Rotation::Daily
+        }
+    }
+
+    /// Checks that every object covers a single value of each date placeholder.
+    ///
+    /// Keys are rendered from the start of the rotation window, so with `{hour}` and daily
+    /// rotation every record of the day would land under `hour=00`.
+    ///
+    /// # Arguments
+    /// * `rotation` - The rotation window
+    ///
+    /// # Returns
+    /// * `Ok(())` - If the window divides the period of every date placeholder
+    /// * `Err(TracingS3Error)` - Naming the first placeholder finer than the window
+    pub fn check_rotation(&self, rotation: Rotation) -> Result<()> {
+        let window_ms = rotation.window().as_millis().max(1);
+        for segment in &self.segments {
+            if let Segment::Placeholder(placeholder) = segment
+                && let Some(period) = placeholder.period()
+                && period.as_millis() % window_ms != 0
+            {
+                return Err(TracingS3Error::invalid_config(
+                    "key_template",
+                    format!(
+                        "Key template uses `{{{}}}`, which changes within the rotation window \
+                         of {rotation:?}; use a window that divides {}s",
+                        placeholder.name(),
+                        period.as_secs()
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the object key.
+    ///
+    /// # Arguments
+    /// * `context` - The values to substitute
+    ///
+    /// # Returns
+    /// The object key. A missing service renders as `unknown`.
+    pub fn render(&self, context: &KeyContext) -> String {
+        let mut key = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => key.push_str(literal),
+                Segment::Placeholder(placeholder) => {
+                    let date = context.window_start;
+                    let value = match placeholder {
+                        Placeholder::Prefix => context.prefix.to_string(),
+                        Placeholder::Postfix => context.postfix.to_string(),
+                        Placeholder::Date => date.format("%Y-%m-%d").to_string(),
+                        Placeholder::Year => date.format("%Y").to_string(),
+                        Placeholder::Month => date.format("%m").to_string(),
+                        Placeholder::Day => date.format("%d").to_string(),
+                        Placeholder::Hour => date.format("%H").to_string(),
+                        Placeholder::Minute => date.format("%M").to_string(),
+                        Placeholder::Hostname => context.hostname.replace('/', "_"),
+                        Placeholder::Pid => context.pid.to_string(),
+                        Placeholder::Service => context.service.unwrap_or("unknown").to_string(),
+                        Placeholder::Nonce => context.nonce.to_string(),
+                        Placeholder::Part => context.part.to_string(),
+                        Placeholder::Level => context.level.as_str().to_lowercase(),
+                    };
+                    key.push_str(&value);
+                }
+            }
+        }
+        key
+    }
+}
+
+impl Default for KeyTemplate {
+    /// The [`DEFAULT_KEY_TEMPLATE`] layout.
+    fn default() -> Self {
+        Self::new(DEFAULT_KEY_TEMPLATE).expect("Default key template is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::key_template::{KeyContext, KeyTemplate};
+    use crate::config::rotation::Rotation;
+    use chrono::NaiveDateTime;
+    use std::time::Duration;
+    use tracing::Level;
+
+    fn context() -> KeyContext<'static> {
+        KeyContext {
+            prefix: "app",
+            postfix: "log",
+            nonce: "nonce",
+            part: 3,
+            window_start: NaiveDateTime::parse_from_str("2024-01-02 05:07:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            hostname: "host",
+            pid: 42,
+            service: Some("api"),
+            level: Level::ERROR,
+        }
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        assert_eq!(
+            KeyTemplate::default().render(&context()),
+            "2024-01-02/3/app-nonce.log"
+        );
+        assert_eq!(
+            KeyTemplate::hive().render(&context()),
+            "app/year=2024/month=01/day=02/hour=05/host-42-nonce-3.log"
+        );
+        let template =
+            KeyTemplate::new("{service}/{level}/{{{minute}}}/{part}-{pid}.{postfix}").unwrap();
+        assert_eq!(template.render(&context()), "api/error/{07}/3-42.log");
+        assert!(template.uses_service());
+        assert!(template.uses_level());
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!(KeyTemplate::new("{prefix}/{part}/{unknown}").is_err());
+        assert!(KeyTemplate::new("{prefix}/{part").is_err());
+        assert!(KeyTemplate::new("{prefix}}/{part}").is_err());
+        assert!(KeyTemplate::new("{prefix}/{nonce}").is_err());
+        assert!(KeyTemplate::new("/{prefix}/{part}").is_err());
+        assert!(KeyTemplate::new("{prefix}/{part}").is_ok());
+    }
+
+    #[test]
+    fn date_placeholders_must_not_change_within_a_window() {
+        let hive = KeyTemplate::hive();
+        assert_eq!(hive.default_rotation(), Rotation::Hourly);
+        assert!(hive.check_rotation(Rotation::Hourly).is_ok());
+        assert!(hive.check_rotation(Rotation::Minutely).is_ok());
+        assert!(
+            hive.check_rotation(Rotation::Custom(Duration::from_secs(15 * 60)))
+                .is_ok()
+        );
+        let err = hive.check_rotation(Rotation::Daily).unwrap_err();
+        assert_eq!(err.field(), Some("key_template"));
+        assert!(err.to_string().contains("{hour}"), "{err}");
+        assert!(
+            hive.check_rotation(Rotation::Custom(Duration::from_secs(90 * 60)))
+                .is_err()
+        );
+        assert_eq!(KeyTemplate::default().default_rotation(), Rotation::Daily);
+        assert!(
+            KeyTemplate::new("{minute}/{part}")
+                .unwrap()
+                .check_rotation(Rotation::Hourly)
+                .is_err()
+        );
+    }
+}
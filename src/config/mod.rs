@@ -1,2 +1,11 @@
+pub mod builder;
+pub mod compression;
+pub mod credentials_source;
+#[cfg(feature = "serde")]
+pub mod file_config;
+pub mod key_template;
+pub mod retry_policy;
+pub mod rotation;
 pub mod tracing_s3_config;
 pub mod types;
+pub mod worker_runtime;
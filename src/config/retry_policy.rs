@@ -0,0 +1,136 @@
+use crate::error::{Result, TracingS3Error};
+use std::time::Duration;
+
+/// Retry policy for failed S3 uploads.
+/// Controls how many times an append is attempted and how long to back off between attempts,
+/// using exponential backoff capped at a maximum delay, optionally with full jitter.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay_ms: u64,
+    max_delay_ms: u64,
+    jitter: bool,
+}
+
+impl RetryPolicy {
+    /// Creates a new RetryPolicy instance.
+    ///
+    /// # Arguments
+    /// * `max_attempts` - Total number of attempts, including the first one (must be at least 1)
+    /// * `base_delay_ms` - Delay before the first retry in milliseconds (must be greater than 0)
+    /// * `max_delay_ms` - Upper bound for any single delay in milliseconds (at least `base_delay_ms`)
+    /// * `jitter` - Whether to randomize each delay between 0 and its computed value
+    ///
+    /// # Returns
+    /// * `Ok(RetryPolicy)` - If the values are valid
+    /// * `Err(TracingS3Error)` - If any of the values is out of range
+    pub fn new(
+        max_attempts: u32,
+        base_delay_ms: u64,
+        max_delay_ms: u64,
+        jitter: bool,
+    ) -> Result<Self> {
+        if max_attempts == 0 {
+            return Err(TracingS3Error::invalid_config(
+                "retry_policy",
+                "Max attempts must be larger than 0",
+            ));
+        } else if base_delay_ms == 0 {
+            return Err(TracingS3Error::invalid_config(
+                "retry_policy",
+                "Base delay must be larger than 0",
+            ));
+        } else if max_delay_ms < base_delay_ms {
+            return Err(TracingS3Error::invalid_config(
+                "retry_policy",
+                "Max delay must not be smaller than the base delay",
+            ));
+        }
+        Ok(Self {
+            max_attempts,
+            base_delay_ms,
+            max_delay_ms,
+            jitter,
+        })
+    }
+
+    /// Returns a policy that makes a single attempt and never retries.
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the total number of attempts, including the first one.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Computes the delay to wait after the given failed attempt.
+    ///
+    /// # Arguments
+    /// * `attempt` - The 1-based number of the attempt that just failed
+    ///
+    /// # Returns
+    /// `base_delay * 2^(attempt - 1)` capped at the max delay, randomized when jitter is enabled
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(63);
+        let delay_ms = self
+            .base_delay_ms
+            .saturating_mul(1_u64 << exponent)
+            .min(self.max_delay_ms);
+        if self.jitter {
+            Duration::from_millis(fastrand::u64(0..=delay_ms))
+        } else {
+            Duration::from_millis(delay_ms)
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Five attempts, starting at 100ms and backing off up to 5 seconds, with jitter.
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay_ms: 100,
+            max_delay_ms: 5_000,
+            jitter: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::retry_policy::RetryPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn validates_values() {
+        assert!(RetryPolicy::new(0, 100, 1_000, false).is_err());
+        assert!(RetryPolicy::new(3, 0, 1_000, false).is_err());
+        assert!(RetryPolicy::new(3, 100, 50, false).is_err());
+        assert!(RetryPolicy::new(3, 100, 100, false).is_ok());
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_cap() {
+        let policy = RetryPolicy::new(10, 100, 1_000, false).unwrap();
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+        assert_eq!(policy.delay(5), Duration::from_millis(1_000));
+        assert_eq!(policy.delay(200), Duration::from_millis(1_000));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy = RetryPolicy::new(10, 100, 1_000, true).unwrap();
+        for attempt in 1..10 {
+            let cap = RetryPolicy::new(10, 100, 1_000, false)
+                .unwrap()
+                .delay(attempt);
+            assert!(policy.delay(attempt) <= cap);
+        }
+    }
+}
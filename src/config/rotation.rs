@@ -0,0 +1,137 @@
+use crate::error::{Result, TracingS3Error};
+use chrono::{DateTime, NaiveDateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use std::time::Duration;
+
+/// Time window after which the layer starts writing to a new object.
+/// Windows are aligned to the wall clock of the configured timezone, e.g. an hourly window
+/// always starts at minute 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rotation {
+    /// A new object every minute.
+    Minutely,
+    /// A new object every hour.
+    Hourly,
+    /// A new object every day, starting at midnight. The default.
+    #[default]
+    Daily,
+    /// A new object every given duration (millisecond precision), aligned to the Unix epoch
+    /// in the configured timezone. Use [`Rotation::custom`] to validate the duration.
+    Custom(Duration),
+}
+
+impl Rotation {
+    /// Creates a custom rotation window.
+    ///
+    /// # Arguments
+    /// * `window` - The window length (at least 1 millisecond)
+    ///
+    /// # Returns
+    /// * `Ok(Rotation)` - If the window is valid
+    /// * `Err(TracingS3Error)` - If the window is shorter than 1 millisecond
+    pub fn custom(window: Duration) -> Result<Self> {
+        if window.as_millis() == 0 {
+            return Err(TracingS3Error::invalid_config(
+                "rotation",
+                "Rotation window must be at least 1 millisecond",
+            ));
+        }
+        Ok(Rotation::Custom(window))
+    }
+
+    /// Returns the length of the window. Daily windows are 24 hours long, ignoring
+    /// daylight saving time changes.
+    pub fn window(&self) -> Duration {
+        match self {
+            Rotation::Minutely => Duration::from_secs(60),
+            Rotation::Hourly => Duration::from_secs(60 * 60),
+            Rotation::Daily => Duration::from_secs(24 * 60 * 60),
+            Rotation::Custom(window) => *window,
+        }
+    }
+
+    /// Returns the start of the window containing `now`, as wall-clock time in `timezone`.
+    ///
+    /// # Arguments
+    /// * `now` - The instant to find the window for
+    /// * `timezone` - The timezone windows are aligned to
+    pub fn window_start(&self, now: DateTime<Utc>, timezone: Tz) -> NaiveDateTime {
+        let local = now.with_timezone(&timezone).naive_local();
+        match self {
+            Rotation::Minutely => local
+                .with_second(0)
+                .and_then(|t| t.with_nanosecond(0))
+                .unwrap_or(local),
+            Rotation::Hourly => local
+                .with_minute(0)
+                .and_then(|t| t.with_second(0))
+                .and_then(|t| t.with_nanosecond(0))
+                .unwrap_or(local),
+            Rotation::Daily => local.date().and_time(Default::default()),
+            Rotation::Custom(window) => {
+                let window_ms = (window.as_millis() as i64).max(1);
+                let local_ms = local.and_utc().timestamp_millis();
+                DateTime::from_timestamp_millis(local_ms - local_ms.rem_euclid(window_ms))
+                    .map_or(local, |start| start.naive_utc())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::rotation::Rotation;
+    use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+    use chrono_tz::Tz;
+    use std::time::Duration;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        Utc.from_utc_datetime(&naive(s))
+    }
+
+    fn naive(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.3f").unwrap()
+    }
+
+    #[test]
+    fn windows_are_aligned_to_the_wall_clock() {
+        let now = at("2024-01-01 05:42:17.250");
+        assert_eq!(
+            Rotation::Minutely.window_start(now, Tz::UTC),
+            naive("2024-01-01 05:42:00.000")
+        );
+        assert_eq!(
+            Rotation::Hourly.window_start(now, Tz::UTC),
+            naive("2024-01-01 05:00:00.000")
+        );
+        assert_eq!(
+            Rotation::Daily.window_start(now, Tz::UTC),
+            naive("2024-01-01 00:00:00.000")
+        );
+        assert_eq!(
+            Rotation::custom(Duration::from_secs(15 * 60))
+                .unwrap()
+                .window_start(now, Tz::UTC),
+            naive("2024-01-01 05:30:00.000")
+        );
+    }
+
+    #[test]
+    fn windows_follow_the_configured_timezone() {
+        let now = at("2024-01-01 05:42:17.250");
+        assert_eq!(
+            Rotation::Daily.window_start(at("2024-01-01 03:42:17.250"), Tz::America__New_York),
+            naive("2023-12-31 00:00:00.000")
+        );
+        assert_eq!(
+            Rotation::Hourly.window_start(now, Tz::Asia__Kolkata),
+            naive("2024-01-01 11:00:00.000")
+        );
+    }
+
+    #[test]
+    fn custom_window_must_not_be_empty() {
+        assert!(Rotation::custom(Duration::from_micros(999)).is_err());
+        assert!(Rotation::custom(Duration::from_millis(1)).is_ok());
+    }
+}
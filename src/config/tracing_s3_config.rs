@@ -1,14 +1,29 @@
+use crate::config::builder::TracingS3ConfigBuilder;
+use crate::config::compression::Compression;
+use crate::config::key_template::KeyTemplate;
+use crate::config::retry_policy::RetryPolicy;
+use crate::config::rotation::Rotation;
 use crate::config::types::{
-    Bucket, BufferSizeLimitKb, CronIntervalInMs, Endpoint, ObjectSizeLimitMb, Postfix, Prefix,
+    Bucket, BufferSizeLimitKb, ChannelCapacity, CronIntervalInMs, Endpoint, ObjectSizeLimitMb,
+    Postfix, Prefix, SpoolSizeLimitMb,
 };
-use aws_credential_types::Credentials;
+use crate::config::worker_runtime::WorkerRuntime;
+use crate::error::Result;
+use crate::layer::event_queue::OverflowPolicy;
+use crate::sink::s3_sink::UploadStrategy;
+use crate::spool::Spool;
 use aws_sdk_s3::Client;
-use aws_types::region::Region;
-use dotenv::dotenv;
-use std::env;
+use chrono_tz::Tz;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::Level;
+use tracing_subscriber::fmt::format::FmtSpan;
 
 /// Configuration for the S3 tracing layer.
 /// Contains all necessary information to connect to AWS S3 and configure logging behavior.
+///
+/// Create it with [`TracingS3Config::builder`], the one place every setting is documented and
+/// validated. The `with_*` mutators of a built configuration are deprecated.
 #[derive(Debug)]
 pub struct TracingS3Config {
     pub aws_client: Client,
@@ -18,8 +33,33 @@ pub struct TracingS3Config {
     pub object_size_limit_mb: u64,
     pub cron_interval_in_ms: u64,
     pub buffer_size_limit_kb: u64,
+    pub shutdown_timeout_in_ms: u64,
+    pub retry_policy: RetryPolicy,
+    pub spool: Option<Arc<Mutex<Spool>>>,
+    pub channel_capacity: Option<usize>,
+    pub overflow_policy: OverflowPolicy,
+    pub upload_strategy: UploadStrategy,
+    pub rotation: Rotation,
+    pub timezone: Tz,
+    pub key_template: KeyTemplate,
+    pub service: Option<String>,
+    pub compression: Compression,
+    pub record_separator: String,
+    pub denied_targets: Vec<String>,
+    pub current_span: bool,
+    pub span_list: bool,
+    pub span_events: FmtSpan,
+    pub trace_context: bool,
+    pub flush_on_level: Option<Level>,
+    pub flush_debounce_in_ms: u64,
+    pub worker_runtime: WorkerRuntime,
 }
 
+/// Default time to wait for the final upload when the layer shuts down.
+pub const DEFAULT_SHUTDOWN_TIMEOUT_IN_MS: u64 = 5_000;
+/// Default minimum time between two uploads triggered by `flush_on_level`.
+pub const DEFAULT_FLUSH_DEBOUNCE_IN_MS: u64 = 1_000;
+
 impl TracingS3Config {
     /// Creates a new TracingS3Config instance with the provided parameters.
     ///
@@ -28,6 +68,10 @@ impl TracingS3Config {
     /// - `S3_TRACING_BUCKET` for the S3 bucket name
     /// - `S3_TRACING_AWS_ACCESS_KEY_ID` for the AWS access key
     /// - `S3_TRACING_AWS_SECRET_ACCESS_KEY` for the AWS secret key
+    /// - `S3_TRACING_AWS_SESSION_TOKEN` for the session token of temporary keys
+    ///
+    /// Without access keys, credentials come from the AWS default provider chain. Use
+    /// [`TracingS3Config::builder`] to assume a role instead.
     ///
     /// # Arguments
     /// * `aws_region` - Optional AWS region override
@@ -43,7 +87,7 @@ impl TracingS3Config {
     ///
     /// # Returns
     /// * `Ok(TracingS3Config)` - If configuration is valid and AWS client can be created
-    /// * `Err(anyhow::Error)` - If configuration is invalid or AWS client creation fails
+    /// * `Err(TracingS3Error)` - If configuration is invalid or AWS client creation fails
     #[allow(clippy::too_many_arguments)]
     pub async fn new<'a>(
         aws_region: Option<&str>,
@@ -56,46 +100,230 @@ impl TracingS3Config {
         object_size_limit_mb: ObjectSizeLimitMb,
         cron_interval_in_ms: CronIntervalInMs,
         buffer_size_limit_kb: BufferSizeLimitKb,
-    ) -> anyhow::Result<Self> {
-        dotenv().ok();
-        let region = Region::new(
-            aws_region
-                .unwrap_or(&env::var("S3_TRACING_AWS_REGION").unwrap_or("us-west-2".to_string()))
-                .to_string(),
-        );
-        let bucket = match bucket.0 {
-            Some(bucket) => bucket.to_string(),
-            None => env::var("S3_TRACING_BUCKET")?,
-        };
-        let aws_access_key = match aws_access_key {
-            Some(access_key) => access_key.to_string(),
-            None => env::var("S3_TRACING_AWS_ACCESS_KEY_ID")?,
-        };
-        let aws_secret_access_key = match aws_secret_access_key {
-            Some(secret_access_key) => secret_access_key.to_string(),
-            None => env::var("S3_TRACING_AWS_SECRET_ACCESS_KEY")?,
-        };
-        let credentials =
-            Credentials::new(aws_access_key, aws_secret_access_key, None, None, "AWS");
-        let mut config_builder = aws_sdk_s3::Config::builder()
-            .behavior_version_latest()
-            .credentials_provider(credentials.clone())
-            .express_credentials_provider(credentials)
-            .region(region);
-        config_builder = match endpoint.0 {
-            Some(endpoint) => config_builder.endpoint_url(endpoint),
-            None => config_builder,
-        };
-        let config = config_builder.build();
-        let aws_client = Client::from_conf(config);
-        Ok(Self {
-            aws_client,
-            bucket,
-            prefix: prefix.0.to_string(),
-            postfix: postfix.0.to_string(),
-            object_size_limit_mb: object_size_limit_mb.inner(),
-            cron_interval_in_ms: cron_interval_in_ms.inner(),
-            buffer_size_limit_kb: buffer_size_limit_kb.inner(),
-        })
+    ) -> Result<Self> {
+        let mut builder = Self::builder()
+            .prefix(prefix.0)
+            .postfix(postfix.0)
+            .object_size_limit_mb(object_size_limit_mb.inner())
+            .cron_interval_in_ms(cron_interval_in_ms.inner())
+            .buffer_size_limit_kb(buffer_size_limit_kb.inner());
+        if let Some(region) = aws_region {
+            builder = builder.region(region);
+        }
+        if let Some(access_key) = aws_access_key {
+            builder = builder.access_key_id(access_key);
+        }
+        if let Some(secret_access_key) = aws_secret_access_key {
+            builder = builder.secret_access_key(secret_access_key);
+        }
+        if let Some(bucket) = bucket.0 {
+            builder = builder.bucket(bucket);
+        }
+        if let Some(endpoint) = endpoint.0 {
+            builder = builder.endpoint(endpoint);
+        }
+        builder.build().await
+    }
+
+    /// Creates a builder with named setters and defaults for every optional setting.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # async fn example() -> Result<(), tracing_s3::error::TracingS3Error> {
+    /// use tracing_s3::config::tracing_s3_config::TracingS3Config;
+    ///
+    /// let config = TracingS3Config::builder()
+    ///     .bucket("my-bucket--usw2-az1--x-s3")
+    ///     .prefix("app-logs")
+    ///     .cron_interval_in_ms(1_000)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn builder() -> TracingS3ConfigBuilder {
+        TracingS3ConfigBuilder::default()
+    }
+
+    /// Sets how long shutting down the layer may wait for the final upload, see
+    /// [`TracingS3ConfigBuilder::shutdown_timeout_in_ms`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::shutdown_timeout_in_ms`")]
+    pub fn with_shutdown_timeout_in_ms(mut self, shutdown_timeout_in_ms: u64) -> Self {
+        self.shutdown_timeout_in_ms = shutdown_timeout_in_ms;
+        self
+    }
+
+    /// Sets the retry policy, see [`TracingS3ConfigBuilder::retry_policy`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::retry_policy`")]
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Enables the local disk spool, see [`TracingS3ConfigBuilder::spool`].
+    ///
+    /// # Returns
+    /// * `Ok(TracingS3Config)` - If the spool directory is usable
+    /// * `Err(TracingS3Error)` - If the spool directory cannot be created or read
+    #[deprecated(note = "use `TracingS3ConfigBuilder::spool`")]
+    pub fn with_spool(
+        mut self,
+        dir: impl AsRef<Path>,
+        size_limit_mb: SpoolSizeLimitMb,
+    ) -> Result<Self> {
+        self.spool = Some(TracingS3ConfigBuilder::open_spool(dir, size_limit_mb)?);
+        Ok(self)
+    }
+
+    /// Bounds the event channel, see [`TracingS3ConfigBuilder::channel_capacity`] and
+    /// [`TracingS3ConfigBuilder::overflow_policy`].
+    #[deprecated(
+        note = "use `TracingS3ConfigBuilder::channel_capacity` and `TracingS3ConfigBuilder::overflow_policy`"
+    )]
+    pub fn with_bounded_channel(
+        mut self,
+        capacity: ChannelCapacity,
+        overflow_policy: OverflowPolicy,
+    ) -> Self {
+        self.channel_capacity = Some(capacity.inner());
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    /// Sets how logs are written to the bucket, see
+    /// [`TracingS3ConfigBuilder::upload_strategy`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::upload_strategy`")]
+    pub fn with_upload_strategy(mut self, upload_strategy: UploadStrategy) -> Self {
+        self.upload_strategy = upload_strategy;
+        self
+    }
+
+    /// Sets the rotation window, see [`TracingS3ConfigBuilder::rotation`]. Unlike the builder
+    /// setter, the window is not checked against the key template.
+    #[deprecated(
+        note = "use `TracingS3ConfigBuilder::rotation`, which is checked against the key template"
+    )]
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Sets the timezone of rotation windows and key dates, see
+    /// [`TracingS3ConfigBuilder::timezone`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::timezone`")]
+    pub fn with_timezone(mut self, timezone: Tz) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    /// Sets the value of the `{service}` key template placeholder, see
+    /// [`TracingS3ConfigBuilder::service`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::service`")]
+    pub fn with_service(mut self, service: &str) -> Self {
+        self.service = Some(service.to_string());
+        self
+    }
+
+    /// Sets the layout of object keys, see [`TracingS3ConfigBuilder::key_template`].
+    ///
+    /// The template is checked against the service and rotation already set, so it depends
+    /// on the order of the calls; the builder setter does not.
+    ///
+    /// # Returns
+    /// * `Ok(TracingS3Config)` - If the template can be rendered
+    /// * `Err(TracingS3Error)` - If the template uses `{service}` but no service is set, or a
+    ///   date placeholder finer than the rotation window, see [`KeyTemplate::check_rotation`]
+    #[deprecated(
+        note = "use `TracingS3ConfigBuilder::key_template`, which does not depend on the order of the setters"
+    )]
+    pub fn with_key_template(mut self, key_template: KeyTemplate) -> Result<Self> {
+        TracingS3ConfigBuilder::check_key_template(
+            &key_template,
+            self.service.as_deref(),
+            self.rotation,
+        )?;
+        self.key_template = key_template;
+        Ok(self)
+    }
+
+    /// Enables compression of uploaded logs, see [`TracingS3ConfigBuilder::compression`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::compression`")]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the string written after every record, see
+    /// [`TracingS3ConfigBuilder::record_separator`].
+    ///
+    /// # Returns
+    /// * `Ok(TracingS3Config)` - If the separator is valid
+    /// * `Err(TracingS3Error)` - If the separator is empty
+    #[deprecated(note = "use `TracingS3ConfigBuilder::record_separator`")]
+    pub fn with_record_separator(mut self, record_separator: &str) -> Result<Self> {
+        TracingS3ConfigBuilder::check_record_separator(record_separator)?;
+        self.record_separator = record_separator.to_string();
+        Ok(self)
+    }
+
+    /// Adds the current span to every record, see [`TracingS3ConfigBuilder::current_span`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::current_span`")]
+    pub fn with_current_span(mut self, current_span: bool) -> Self {
+        self.current_span = current_span;
+        self
+    }
+
+    /// Adds the span stack to every record, see [`TracingS3ConfigBuilder::span_list`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::span_list`")]
+    pub fn with_span_list(mut self, span_list: bool) -> Self {
+        self.span_list = span_list;
+        self
+    }
+
+    /// Adds trace IDs to every record, see [`TracingS3ConfigBuilder::trace_context`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::trace_context`")]
+    pub fn with_trace_context(mut self, trace_context: bool) -> Self {
+        self.trace_context = trace_context;
+        self
+    }
+
+    /// Selects the recorded span lifecycle events, see
+    /// [`TracingS3ConfigBuilder::span_events`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::span_events`")]
+    pub fn with_span_events(mut self, span_events: FmtSpan) -> Self {
+        self.span_events = span_events;
+        self
+    }
+
+    /// Uploads as soon as an event at `level` or more severe is buffered, see
+    /// [`TracingS3ConfigBuilder::flush_on_level`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::flush_on_level`")]
+    pub fn with_flush_on_level(mut self, level: Level) -> Self {
+        self.flush_on_level = Some(level);
+        self
+    }
+
+    /// Sets the minimum time between uploads triggered by `flush_on_level`, see
+    /// [`TracingS3ConfigBuilder::flush_debounce_in_ms`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::flush_debounce_in_ms`")]
+    pub fn with_flush_debounce_in_ms(mut self, flush_debounce_in_ms: u64) -> Self {
+        self.flush_debounce_in_ms = flush_debounce_in_ms;
+        self
+    }
+
+    /// Selects where the background worker runs, see
+    /// [`TracingS3ConfigBuilder::worker_runtime`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::worker_runtime`")]
+    pub fn with_worker_runtime(mut self, worker_runtime: WorkerRuntime) -> Self {
+        self.worker_runtime = worker_runtime;
+        self
+    }
+
+    /// Replaces the list of event targets the layer never records, see
+    /// [`TracingS3ConfigBuilder::denied_targets`].
+    #[deprecated(note = "use `TracingS3ConfigBuilder::denied_targets`")]
+    pub fn with_denied_targets(mut self, denied_targets: Vec<String>) -> Self {
+        self.denied_targets = denied_targets;
+        self
     }
 }
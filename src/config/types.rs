@@ -1,4 +1,4 @@
-use anyhow::anyhow;
+use crate::error::{Result, TracingS3Error};
 
 /// Represents a buffer size limit in kilobytes.
 /// Used to control the maximum amount of log data to buffer in memory before flushing to S3.
@@ -12,12 +12,18 @@ impl BufferSizeLimitKb {
     ///
     /// # Returns
     /// * `Ok(BufferSizeLimitKb)` - If the size limit is valid
-    /// * `Err(anyhow::Error)` - If the size limit is 0 or greater than 50,000 KB
-    pub fn new(size_limit: u64) -> anyhow::Result<Self> {
+    /// * `Err(TracingS3Error)` - If the size limit is 0 or greater than 50,000 KB
+    pub fn new(size_limit: u64) -> Result<Self> {
         if size_limit == 0 {
-            return Err(anyhow!("Value must be larger than 0"));
+            return Err(TracingS3Error::invalid_config(
+                "buffer_size_limit_kb",
+                "Value must be larger than 0",
+            ));
         } else if size_limit > 50_000 {
-            return Err(anyhow!("Value must be smaller than 50,000 (50MB)"));
+            return Err(TracingS3Error::invalid_config(
+                "buffer_size_limit_kb",
+                "Value must be smaller than 50,000 (50MB)",
+            ));
         }
         Ok(Self(size_limit))
     }
@@ -40,12 +46,18 @@ impl ObjectSizeLimitMb {
     ///
     /// # Returns
     /// * `Ok(ObjectSizeLimitMb)` - If the size limit is valid
-    /// * `Err(anyhow::Error)` - If the size limit is 0 or greater than 50,000 MB
-    pub fn new(size_limit: u64) -> anyhow::Result<Self> {
+    /// * `Err(TracingS3Error)` - If the size limit is 0 or greater than 50,000 MB
+    pub fn new(size_limit: u64) -> Result<Self> {
         if size_limit == 0 {
-            return Err(anyhow!("Value must be larger than 0"));
+            return Err(TracingS3Error::invalid_config(
+                "object_size_limit_mb",
+                "Value must be larger than 0",
+            ));
         } else if size_limit > 50_000 {
-            return Err(anyhow!("Value must be smaller than 50,000 (50GB)"));
+            return Err(TracingS3Error::invalid_config(
+                "object_size_limit_mb",
+                "Value must be smaller than 50,000 (50GB)",
+            ));
         }
         Ok(Self(size_limit))
     }
@@ -56,6 +68,69 @@ impl ObjectSizeLimitMb {
     }
 }
 
+/// Represents a spool size limit in megabytes.
+/// Used to cap the disk space taken by batches spooled while S3 is unreachable.
+pub struct SpoolSizeLimitMb(u64);
+
+impl SpoolSizeLimitMb {
+    /// Creates a new SpoolSizeLimitMb instance.
+    ///
+    /// # Arguments
+    /// * `size_limit` - The spool size limit in megabytes (1-50,000 MB)
+    ///
+    /// # Returns
+    /// * `Ok(SpoolSizeLimitMb)` - If the size limit is valid
+    /// * `Err(TracingS3Error)` - If the size limit is 0 or greater than 50,000 MB
+    pub fn new(size_limit: u64) -> Result<Self> {
+        if size_limit == 0 {
+            return Err(TracingS3Error::invalid_config(
+                "spool",
+                "Value must be larger than 0",
+            ));
+        } else if size_limit > 50_000 {
+            return Err(TracingS3Error::invalid_config(
+                "spool",
+                "Value must be smaller than 50,000 (50GB)",
+            ));
+        }
+        Ok(Self(size_limit))
+    }
+
+    /// Returns the inner spool size limit value in megabytes.
+    pub fn inner(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Represents the capacity of the event channel, in events.
+/// Used to bound the memory taken by events waiting for the background worker.
+pub struct ChannelCapacity(usize);
+
+impl ChannelCapacity {
+    /// Creates a new ChannelCapacity instance.
+    ///
+    /// # Arguments
+    /// * `capacity` - The maximum number of queued events (must be greater than 0)
+    ///
+    /// # Returns
+    /// * `Ok(ChannelCapacity)` - If the capacity is valid
+    /// * `Err(TracingS3Error)` - If the capacity is 0
+    pub fn new(capacity: usize) -> Result<Self> {
+        if capacity == 0 {
+            return Err(TracingS3Error::invalid_config(
+                "channel_capacity",
+                "Value must be larger than 0",
+            ));
+        }
+        Ok(Self(capacity))
+    }
+
+    /// Returns the inner channel capacity.
+    pub fn inner(&self) -> usize {
+        self.0
+    }
+}
+
 /// Represents a cron interval in milliseconds.
 /// Used to control how frequently the background task flushes buffered logs to S3.
 pub struct CronIntervalInMs(u64);
@@ -68,10 +143,13 @@ impl CronIntervalInMs {
     ///
     /// # Returns
     /// * `Ok(CronIntervalInMs)` - If the interval is valid
-    /// * `Err(anyhow::Error)` - If the interval is 0
-    pub fn new(interval: u64) -> anyhow::Result<Self> {
+    /// * `Err(TracingS3Error)` - If the interval is 0
+    pub fn new(interval: u64) -> Result<Self> {
         if interval == 0 {
-            return Err(anyhow!("Value must be larger than 0"));
+            return Err(TracingS3Error::invalid_config(
+                "cron_interval_in_ms",
+                "Value must be larger than 0",
+            ));
         }
         Ok(Self(interval))
     }
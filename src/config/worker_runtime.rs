@@ -0,0 +1,129 @@
+use crate::error::{Result, TracingS3Error};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// Name of the thread started by [`WorkerRuntime::DedicatedThread`].
+pub const WORKER_THREAD_NAME: &str = "tracing-s3-worker";
+
+/// Where the background worker of the layer runs.
+#[derive(Debug, Clone, Default)]
+pub enum WorkerRuntime {
+    /// Spawns the worker on the Tokio runtime the layer is created in. The default.
+    /// Creating the layer outside of a runtime fails.
+    #[default]
+    Ambient,
+    /// Starts a dedicated thread running its own current-thread runtime, so the layer can be
+    /// created anywhere, e.g. in a synchronous `main` before any runtime exists. The thread
+    /// ends once the worker stops.
+    DedicatedThread,
+    /// Spawns the worker on the runtime of the given handle, which may be used from outside
+    /// that runtime.
+    Handle(Handle),
+    /// Spawns the worker on a dedicated thread that is already running.
+    /// [`TracingS3ConfigBuilder::build_blocking`](crate::config::builder::TracingS3ConfigBuilder::build_blocking)
+    /// starts one for [`WorkerRuntime::DedicatedThread`], so the AWS client is created on the
+    /// runtime the worker runs on.
+    Thread(WorkerThread),
+}
+
+impl WorkerRuntime {
+    /// Spawns the worker future on the selected runtime.
+    ///
+    /// # Arguments
+    /// * `future` - The worker future
+    ///
+    /// # Returns
+    /// * `Ok(JoinHandle<()>)` - A JoinHandle for the worker task, which can be awaited from
+    ///   any runtime
+    /// * `Err(TracingS3Error)` - With [`WorkerRuntime::Ambient`] outside of a Tokio runtime,
+    ///   and with [`WorkerRuntime::DedicatedThread`] if the thread or its runtime cannot be
+    ///   started
+    pub fn spawn<F>(&self, future: F) -> Result<JoinHandle<()>>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match self {
+            WorkerRuntime::Ambient => Ok(ambient_handle()?.spawn(future)),
+            WorkerRuntime::Handle(handle) => Ok(handle.spawn(future)),
+            WorkerRuntime::DedicatedThread => Ok(WorkerThread::start()?.spawn(future)),
+            WorkerRuntime::Thread(thread) => Ok(thread.spawn(future)),
+        }
+    }
+}
+
+/// Returns the handle of the current Tokio runtime, or an error pointing to the other
+/// [`WorkerRuntime`] options.
+pub(crate) fn ambient_handle() -> Result<Handle> {
+    Handle::try_current().map_err(|_| {
+        TracingS3Error::invalid_config(
+            "worker_runtime",
+            "No Tokio runtime is running; use WorkerRuntime::DedicatedThread or \
+             WorkerRuntime::Handle outside of a runtime",
+        )
+    })
+}
+
+/// A thread running its own current-thread Tokio runtime, see
+/// [`WorkerRuntime::DedicatedThread`].
+///
+/// The thread stops once every clone of this value is dropped, including the ones held by
+/// the tasks spawned with [`WorkerThread::spawn`].
+#[derive(Debug, Clone)]
+pub struct WorkerThread {
+    handle: Handle,
+    // Dropping the last clone closes the channel the thread waits on.
+    _stop: Arc<oneshot::Sender<()>>,
+}
+
+impl WorkerThread {
+    /// Starts the thread and its runtime.
+    ///
+    /// # Returns
+    /// * `Ok(WorkerThread)` - If the thread is running
+    /// * `Err(TracingS3Error)` - If the runtime or the thread cannot be started
+    pub fn start() -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| {
+                TracingS3Error::Worker(format!("Failed to build the log worker runtime: {err}"))
+            })?;
+        let handle = runtime.handle().clone();
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        std::thread::Builder::new()
+            .name(WORKER_THREAD_NAME.to_string())
+            .spawn(move || {
+                let _ = runtime.block_on(stop_rx);
+            })
+            .map_err(|err| {
+                TracingS3Error::Worker(format!("Failed to start the log worker thread: {err}"))
+            })?;
+        Ok(Self {
+            handle,
+            _stop: Arc::new(stop_tx),
+        })
+    }
+
+    /// Returns the handle of the thread's runtime.
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    /// Spawns a future on the thread, keeping the thread running until the future completes.
+    ///
+    /// # Arguments
+    /// * `future` - The future to run
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let keep_alive = self.clone();
+        self.handle.spawn(async move {
+            future.await;
+            drop(keep_alive);
+        })
+    }
+}
@@ -0,0 +1,239 @@
+use aws_credential_types::provider::error::CredentialsError;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use std::error::Error;
+use std::fmt;
+
+/// A boxed error from the AWS SDK or a custom [`LogSink`](crate::sink::LogSink).
+pub type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
+/// Result type returned throughout the crate.
+pub type Result<T> = std::result::Result<T, TracingS3Error>;
+
+/// Error returned by the public API of this crate.
+#[derive(Debug, thiserror::Error)]
+pub enum TracingS3Error {
+    /// A setting is out of range or malformed. `field` is the name of its builder setter.
+    #[error("Invalid `{field}`: {message}")]
+    InvalidConfig {
+        field: &'static str,
+        message: String,
+    },
+    /// A required setting was neither configured nor found in the environment.
+    #[error("Missing `{field}`: configure it or set `{env_var}`")]
+    MissingConfig {
+        field: &'static str,
+        env_var: &'static str,
+    },
+    /// AWS credentials could not be resolved, e.g. no provider in the default chain
+    /// returned credentials or assuming the role was denied. `retryable` is false only if
+    /// no credentials are configured or their configuration is invalid.
+    #[error("Failed to resolve AWS credentials: {message}")]
+    Credentials {
+        retryable: bool,
+        message: String,
+        #[source]
+        source: BoxError,
+    },
+    /// An S3 request failed. `status` is the HTTP status code of the response, if one was
+    /// received, and `code` the S3 error code (e.g. `AccessDenied`).
+    #[error("S3 request failed{}: {message}", StatusSuffix(*.status))]
+    S3 {
+        status: Option<u16>,
+        code: Option<String>,
+        retryable: bool,
+        message: String,
+        #[source]
+        source: BoxError,
+    },
+    /// A log record or spooled batch could not be serialized or parsed.
+    #[error("Serialization failed: {0}")]
+    Serialization(#[from] serde_json::Error),
+    /// A local file (spool segment, [`FsSink`](crate::sink::fs_sink::FsSink) object) or
+    /// compression stream could not be read or written.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The background worker is gone, or did not answer before the shutdown timeout.
+    #[error("{0}")]
+    Worker(String),
+    /// An error raised by a custom [`LogSink`](crate::sink::LogSink).
+    #[error(transparent)]
+    Sink(BoxError),
+}
+
+impl TracingS3Error {
+    /// Creates a [`TracingS3Error::InvalidConfig`] error.
+    ///
+    /// # Arguments
+    /// * `field` - The name of the builder setter whose value is invalid
+    /// * `message` - What is wrong with the value
+    pub fn invalid_config(field: &'static str, message: impl fmt::Display) -> Self {
+        TracingS3Error::InvalidConfig {
+            field,
+            message: message.to_string(),
+        }
+    }
+
+    /// Returns the name of the builder setter a configuration error is about.
+    pub fn field(&self) -> Option<&'static str> {
+        match self {
+            TracingS3Error::InvalidConfig { field, .. }
+            | TracingS3Error::MissingConfig { field, .. } => Some(field),
+            _ => None,
+        }
+    }
+
+    /// Returns the HTTP status code of a failed S3 request, if a response was received.
+    pub fn status_code(&self) -> Option<u16> {
+        match self {
+            TracingS3Error::S3 { status, .. } => *status,
+            _ => None,
+        }
+    }
+
+    /// Returns whether the error is transient: timeouts, connection failures, throttling,
+    /// 5xx responses, write offset conflicts and credential providers that timed out or
+    /// failed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            TracingS3Error::S3 {
+                retryable: true,
+                ..
+            } | TracingS3Error::Credentials {
+                retryable: true,
+                ..
+            }
+        )
+    }
+
+    /// Wraps a transport-level error, such as a response body that could not be read.
+    pub(crate) fn s3_transport(err: impl Error + Send + Sync + 'static) -> Self {
+        TracingS3Error::S3 {
+            status: None,
+            code: None,
+            retryable: true,
+            message: err.to_string(),
+            source: Box::new(err),
+        }
+    }
+}
+
+impl<E> From<SdkError<E, HttpResponse>> for TracingS3Error
+where
+    E: ProvideErrorMetadata + Error + Send + Sync + 'static,
+{
+    fn from(err: SdkError<E, HttpResponse>) -> Self {
+        let message = DisplayErrorContext(&err).to_string();
+        if let Some(credentials_error) = find_credentials_error(&err) {
+            return TracingS3Error::Credentials {
+                retryable: !matches!(
+                    credentials_error,
+                    CredentialsError::CredentialsNotLoaded(_)
+                        | CredentialsError::InvalidConfiguration(_)
+                ),
+                message,
+                source: Box::new(err),
+            };
+        }
+        let status = err
+            .raw_response()
+            .map(|response| response.status().as_u16());
+        let code = err.code().map(str::to_string);
+        let retryable = match &err {
+            SdkError::TimeoutError(_)
+            | SdkError::DispatchFailure(_)
+            | SdkError::ResponseError(_) => true,
+            SdkError::ServiceError(_) => {
+                status.is_some_and(|status| status == 429 || status >= 500)
+                    || matches!(
+                        code.as_deref(),
+                        Some(
+                            "SlowDown"
+                                | "RequestTimeout"
+                                | "InternalError"
+                                | "ServiceUnavailable"
+                                | "InvalidWriteOffset"
+                        )
+                    )
+            }
+            _ => false,
+        };
+        TracingS3Error::S3 {
+            status,
+            code,
+            retryable,
+            message,
+            source: Box::new(err),
+        }
+    }
+}
+
+/// Returns the credentials error `err` was caused by, if any.
+fn find_credentials_error<'a>(err: &'a (dyn Error + 'static)) -> Option<&'a CredentialsError> {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(credentials_error) = err.downcast_ref::<CredentialsError>() {
+            return Some(credentials_error);
+        }
+        source = err.source();
+    }
+    None
+}
+
+struct StatusSuffix(Option<u16>);
+
+impl fmt::Display for StatusSuffix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(status) => write!(f, " with status {status}"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::error::ConnectorError;
+    use aws_sdk_s3::operation::put_object::PutObjectError;
+    use std::time::Duration;
+
+    fn from_credentials_error(err: CredentialsError) -> TracingS3Error {
+        SdkError::<PutObjectError, HttpResponse>::construction_failure(err).into()
+    }
+
+    #[test]
+    fn failing_credential_providers_are_retryable() {
+        for err in [
+            CredentialsError::provider_timed_out(Duration::from_secs(5)),
+            CredentialsError::provider_error("IMDS is unreachable"),
+            CredentialsError::unhandled("unexpected response"),
+        ] {
+            let err = from_credentials_error(err);
+            assert!(matches!(err, TracingS3Error::Credentials { .. }), "{err:?}");
+            assert!(err.is_retryable(), "{err}");
+        }
+
+        let dispatch_failure = ConnectorError::other(
+            Box::new(CredentialsError::provider_timed_out(Duration::from_secs(5))),
+            None,
+        );
+        let err: TracingS3Error =
+            SdkError::<PutObjectError, HttpResponse>::dispatch_failure(dispatch_failure).into();
+        assert!(matches!(err, TracingS3Error::Credentials { .. }), "{err:?}");
+        assert!(err.is_retryable(), "{err}");
+    }
+
+    #[test]
+    fn missing_or_invalid_credentials_are_permanent() {
+        for err in [
+            CredentialsError::not_loaded("no provider returned credentials"),
+            CredentialsError::invalid_configuration("malformed profile"),
+        ] {
+            let err = from_credentials_error(err);
+            assert!(matches!(err, TracingS3Error::Credentials { .. }), "{err:?}");
+            assert!(!err.is_retryable(), "{err}");
+        }
+    }
+}
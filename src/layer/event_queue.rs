@@ -0,0 +1,277 @@
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use tokio::sync::Notify;
+
+/// What to do with a new event when a bounded event queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum OverflowPolicy {
+    /// Discard the incoming event.
+    DropNewest,
+    /// Discard the oldest queued event to make room for the incoming one.
+    DropOldest,
+    /// Block the thread emitting the event until the worker makes room.
+    ///
+    /// Never use this when the worker shares a single-threaded runtime with the code
+    /// that logs: the blocked thread would be the one the worker needs to run on.
+    Block,
+    /// Keep one in every `n` overflowing events (evicting the oldest queued event for it)
+    /// and discard the rest.
+    Sample(u32),
+}
+
+struct QueueState {
+    events: VecDeque<Value>,
+    overflowed: u64,
+    closed: bool,
+}
+
+/// Queue carrying serialized events from the layer to the background worker.
+///
+/// Either unbounded, or bounded with an [`OverflowPolicy`]. Events discarded because
+/// of the policy are counted, so the worker can record the gap in the uploaded logs.
+pub struct EventQueue {
+    state: Mutex<QueueState>,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    not_full: Condvar,
+    not_empty: Notify,
+    dropped: AtomicU64,
+    dropped_total: AtomicU64,
+}
+
+impl EventQueue {
+    /// Creates a queue without a capacity limit.
+    pub fn unbounded() -> Self {
+        Self::new(None, OverflowPolicy::DropNewest)
+    }
+
+    /// Creates a queue holding at most `capacity` events.
+    ///
+    /// # Arguments
+    /// * `capacity` - The maximum number of queued events
+    /// * `policy` - What to do with new events once the queue is full
+    pub fn bounded(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self::new(Some(capacity), policy)
+    }
+
+    fn new(capacity: Option<usize>, policy: OverflowPolicy) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                events: VecDeque::new(),
+                overflowed: 0,
+                closed: false,
+            }),
+            capacity,
+            policy,
+            not_full: Condvar::new(),
+            not_empty: Notify::new(),
+            dropped: AtomicU64::new(0),
+            dropped_total: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues an event, applying the overflow policy if the queue is full.
+    /// Events pushed after the queue was closed are discarded silently.
+    ///
+    /// # Arguments
+    /// * `event` - The event to queue
+    pub fn push(&self, event: Value) {
+        self.enqueue(event, true);
+    }
+
+    /// Queues an event like [`EventQueue::push`], but never waits: under
+    /// [`OverflowPolicy::Block`] the event is dropped (and counted) if the queue is full.
+    ///
+    /// # Arguments
+    /// * `event` - The event to queue
+    pub fn try_push(&self, event: Value) {
+        self.enqueue(event, false);
+    }
+
+    fn enqueue(&self, event: Value, wait: bool) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(capacity) = self.capacity {
+            if wait && self.policy == OverflowPolicy::Block {
+                while !state.closed && state.events.len() >= capacity {
+                    state = self.not_full.wait(state).unwrap();
+                }
+            } else if state.events.len() >= capacity {
+                let keep = match self.policy {
+                    OverflowPolicy::DropOldest => true,
+                    OverflowPolicy::Sample(n) => {
+                        state.overflowed += 1;
+                        state.overflowed.is_multiple_of(u64::from(n.max(1)))
+                    }
+                    OverflowPolicy::DropNewest | OverflowPolicy::Block => false,
+                };
+                self.record_drop();
+                if !keep {
+                    return;
+                }
+                state.events.pop_front();
+            }
+        }
+        if state.closed {
+            return;
+        }
+        state.events.push_back(event);
+        drop(state);
+        self.not_empty.notify_one();
+    }
+
+    /// Removes the oldest event without waiting.
+    pub fn pop(&self) -> Option<Value> {
+        let event = self.state.lock().unwrap().events.pop_front();
+        if event.is_some() {
+            self.not_full.notify_one();
+        }
+        event
+    }
+
+    /// Waits for the next event.
+    ///
+    /// # Returns
+    /// The oldest event, or `None` once the queue is closed and empty
+    pub async fn recv(&self) -> Option<Value> {
+        loop {
+            let notified = self.not_empty.notified();
+            if let Some(event) = self.pop() {
+                return Some(event);
+            }
+            if self.state.lock().unwrap().closed {
+                return None;
+            }
+            notified.await;
+        }
+    }
+
+    /// Closes the queue: further events are discarded and blocked producers are released.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_full.notify_all();
+        self.not_empty.notify_waiters();
+    }
+
+    /// Returns the number of events currently queued.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().events.len()
+    }
+
+    /// Returns true if no events are queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of events dropped since the last call, resetting the counter.
+    pub fn take_dropped(&self) -> u64 {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+
+    /// Returns the total number of events dropped since the queue was created.
+    pub fn dropped_total(&self) -> u64 {
+        self.dropped_total.load(Ordering::Relaxed)
+    }
+
+    fn record_drop(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        self.dropped_total.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::event_queue::{EventQueue, OverflowPolicy};
+    use serde_json::{Value, json};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn drain(queue: &EventQueue) -> Vec<Value> {
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_first_events() {
+        let queue = EventQueue::bounded(2, OverflowPolicy::DropNewest);
+        (0..5).for_each(|i| queue.push(json!(i)));
+        assert_eq!(drain(&queue), vec![json!(0), json!(1)]);
+        assert_eq!(queue.take_dropped(), 3);
+        assert_eq!(queue.take_dropped(), 0);
+        assert_eq!(queue.dropped_total(), 3);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_last_events() {
+        let queue = EventQueue::bounded(2, OverflowPolicy::DropOldest);
+        (0..5).for_each(|i| queue.push(json!(i)));
+        assert_eq!(drain(&queue), vec![json!(3), json!(4)]);
+        assert_eq!(queue.take_dropped(), 3);
+    }
+
+    #[test]
+    fn sample_keeps_one_in_n_overflowing_events() {
+        let queue = EventQueue::bounded(2, OverflowPolicy::Sample(3));
+        (0..8).for_each(|i| queue.push(json!(i)));
+        // Overflowing events are 2..8; the 3rd and 6th of them (4 and 7) are kept.
+        assert_eq!(drain(&queue), vec![json!(4), json!(7)]);
+        assert_eq!(queue.take_dropped(), 6);
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let queue = Arc::new(EventQueue::bounded(1, OverflowPolicy::Block));
+        queue.push(json!(0));
+        let producer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.push(json!(1)))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!producer.is_finished());
+        assert_eq!(queue.pop(), Some(json!(0)));
+        producer.join().unwrap();
+        assert_eq!(drain(&queue), vec![json!(1)]);
+        assert_eq!(queue.dropped_total(), 0);
+    }
+
+    #[test]
+    fn try_push_drops_instead_of_blocking() {
+        let queue = EventQueue::bounded(1, OverflowPolicy::Block);
+        queue.push(json!(0));
+        queue.try_push(json!(1));
+        assert_eq!(queue.take_dropped(), 1);
+        assert_eq!(drain(&queue), vec![json!(0)]);
+    }
+
+    #[test]
+    fn close_releases_blocked_producers() {
+        let queue = Arc::new(EventQueue::bounded(1, OverflowPolicy::Block));
+        queue.push(json!(0));
+        let producer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.push(json!(1)))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        queue.close();
+        producer.join().unwrap();
+        assert_eq!(drain(&queue), vec![json!(0)]);
+    }
+
+    #[tokio::test]
+    async fn recv_returns_none_once_closed_and_empty() {
+        let queue = Arc::new(EventQueue::unbounded());
+        let receiver = {
+            let queue = queue.clone();
+            tokio::spawn(async move { (queue.recv().await, queue.recv().await) })
+        };
+        queue.push(json!(0));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        queue.close();
+        assert_eq!(receiver.await.unwrap(), (Some(json!(0)), None));
+    }
+}
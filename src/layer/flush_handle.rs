@@ -0,0 +1,97 @@
+use crate::error::{Result, TracingS3Error};
+use crate::layer::http_log_layer::WorkerCommand;
+use crate::layer::shutdown_guard::Reply;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+
+/// Cloneable handle that makes the background worker upload everything right away, e.g.
+/// before a deploy hook runs, at the end of a batch job or from a panic handler.
+///
+/// Obtained from [`HttpLogLayer::flush_handle`](crate::layer::http_log_layer::HttpLogLayer::flush_handle)
+/// or [`ShutdownGuard::flush_handle`](crate::layer::shutdown_guard::ShutdownGuard::flush_handle).
+/// A flush drains the events still waiting for the worker and uploads the buffer and the disk
+/// spool, so every event emitted before the call is stored once it returns `Ok`. Events
+/// discarded by the overflow policy of a bounded channel are the exception; they are only
+/// counted. The current objects are not finalized (see
+/// [`LogSink::finalize`](crate::sink::LogSink::finalize)): with
+/// [`UploadStrategy::Multipart`](crate::sink::s3_sink::UploadStrategy::Multipart) the data is
+/// stored as parts of the open upload, and the object becomes visible once it is rotated or
+/// the layer shuts down, unless [`FlushHandle::finalize_blocking`] is used.
+#[derive(Clone)]
+pub struct FlushHandle {
+    command_tx: UnboundedSender<WorkerCommand>,
+}
+
+impl FlushHandle {
+    /// Creates a new FlushHandle for a running worker.
+    ///
+    /// # Arguments
+    /// * `command_tx` - The worker's command channel
+    pub fn new(command_tx: UnboundedSender<WorkerCommand>) -> Self {
+        Self { command_tx }
+    }
+
+    /// Uploads every event emitted so far and waits for the result.
+    ///
+    /// # Returns
+    /// * `Ok(())` - If everything was uploaded (or there was nothing to upload)
+    /// * `Err(TracingS3Error)` - If an upload failed or the worker is no longer running.
+    ///   Payloads that failed with a transient error are kept and retried on the next tick.
+    pub async fn flush(&self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Reply::Async(tx), false)?;
+        rx.await.unwrap_or_else(|_| {
+            Err(TracingS3Error::Worker(
+                "The log worker stopped before replying".to_string(),
+            ))
+        })
+    }
+
+    /// Uploads every event emitted so far, blocking the current thread until the upload
+    /// finished or `timeout` elapsed. Usable outside of any async context.
+    ///
+    /// Inside a multi-threaded Tokio runtime the wait goes through `block_in_place`. On a
+    /// current-thread runtime the worker cannot run while the thread is blocked, so the call
+    /// times out there; use [`FlushHandle::flush`] instead, or give the worker its own thread
+    /// with [`WorkerRuntime::DedicatedThread`](crate::config::worker_runtime::WorkerRuntime::DedicatedThread).
+    ///
+    /// # Arguments
+    /// * `timeout` - The maximum time to wait
+    ///
+    /// # Returns
+    /// * `Ok(())` - If everything was uploaded (or there was nothing to upload)
+    /// * `Err(TracingS3Error)` - If an upload failed, the worker is no longer running or the
+    ///   timeout elapsed
+    pub fn flush_blocking(&self, timeout: Duration) -> Result<()> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.send(Reply::Blocking(tx), false)?;
+        Reply::wait_blocking(rx, timeout)
+    }
+
+    /// Like [`FlushHandle::flush_blocking`], but also finalizes the current objects, so
+    /// everything emitted so far is visible once it returns `Ok`, even if the process then
+    /// exits without shutting the layer down. With
+    /// [`UploadStrategy::Multipart`](crate::sink::s3_sink::UploadStrategy::Multipart) this
+    /// completes the open upload; the next upload to the same object starts a new one that
+    /// copies the completed content.
+    ///
+    /// # Arguments
+    /// * `timeout` - The maximum time to wait
+    ///
+    /// # Returns
+    /// * `Ok(())` - If everything was uploaded and finalized
+    /// * `Err(TracingS3Error)` - If an upload or the finalization failed, the worker is no
+    ///   longer running or the timeout elapsed
+    pub fn finalize_blocking(&self, timeout: Duration) -> Result<()> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.send(Reply::Blocking(tx), true)?;
+        Reply::wait_blocking(rx, timeout)
+    }
+
+    fn send(&self, reply: Reply, finalize: bool) -> Result<()> {
+        self.command_tx
+            .send(WorkerCommand::Flush { reply, finalize })
+            .map_err(|_| TracingS3Error::Worker("The log worker is no longer running".to_string()))
+    }
+}
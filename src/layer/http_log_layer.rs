@@ -1,29 +1,78 @@
+use crate::config::compression::Compression;
+use crate::config::key_template::{KeyContext, KeyTemplate};
+use crate::config::rotation::Rotation;
 use crate::config::tracing_s3_config::TracingS3Config;
-use crate::s3_helpers::S3Helpers;
-use chrono::Local;
-use serde_json::Value;
-use std::sync::Arc;
+use crate::error::{Result, TracingS3Error};
+use crate::layer::event_queue::EventQueue;
+use crate::layer::flush_handle::FlushHandle;
+use crate::layer::reentrancy_guard;
+use crate::layer::shutdown_guard::{Reply, ShutdownGuard};
+use crate::sink::LogSink;
+use crate::sink::s3_sink::S3Sink;
+use crate::spool::{Pushed, Spool};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use serde_json::{Value, json};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::{RwLock, mpsc};
 use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::Level;
+use tracing::dispatcher::WeakDispatch;
 use uuid::Uuid;
 
+/// A batch of buffered log entries destined for one object, returned by [`Output::flush_buffer`].
+#[derive(Debug, Clone)]
+pub struct FlushedBatch {
+    /// The object key the batch is written to, kept when the batch is requeued.
+    pub key: String,
+    /// The log entries, each terminated by the record separator.
+    pub payload: String,
+}
+
+/// The object a buffered entry is written to.
+enum Destination {
+    /// The current object for records of this level.
+    Current(Level),
+    /// A fixed object, for batches put back by [`Output::requeue`].
+    Key(String),
+}
+
+/// Buffered log entries and their total size, always updated together under one lock.
+#[derive(Default)]
+struct Buffer {
+    entries: Vec<(Destination, String)>,
+    size_in_bytes: u64,
+}
+
 /// Represents the output buffer for log data before it's sent to S3.
 /// Manages buffering, naming, and partitioning of log files.
 pub struct Output {
-    name: String,
     size_in_bytes: Arc<AtomicU64>,
-    buffer: Arc<RwLock<Vec<String>>>,
+    buffer: Arc<RwLock<Buffer>>,
     part: Arc<AtomicU64>,
     prefix: String,
     postfix: String,
     nonce: String,
+    rotated: Vec<String>,
+    written: Mutex<Vec<Level>>,
+    rotation: Rotation,
+    timezone: Tz,
+    window_start: NaiveDateTime,
+    key_template: KeyTemplate,
+    hostname: String,
+    pid: u32,
+    service: Option<String>,
+    record_separator: String,
 }
 
 impl Output {
     /// Creates a new Output instance with the specified prefix and postfix.
+    /// Uses the default key template and rotates daily in UTC, see [`Output::with_rotation`]
+    /// and [`Output::with_key_template`].
     ///
     /// # Arguments
     /// * `prefix` - The prefix for log file names
@@ -32,77 +81,278 @@ impl Output {
     /// # Returns
     /// A new Output instance with initialized buffer and metadata
     pub fn new(prefix: &str, postfix: &str) -> Self {
-        let nonce = Uuid::new_v4().to_string();
+        let rotation = Rotation::default();
         Self {
-            name: Self::gen_name(prefix, 0, postfix, &nonce),
-            nonce,
+            nonce: Uuid::new_v4().to_string(),
             prefix: prefix.to_string(),
             postfix: postfix.to_string(),
-            buffer: Arc::new(RwLock::new(Vec::new())),
+            buffer: Arc::new(RwLock::new(Buffer::default())),
             size_in_bytes: Arc::new(AtomicU64::new(0)),
             part: Arc::new(AtomicU64::new(0)),
+            rotated: Vec::new(),
+            written: Mutex::new(Vec::new()),
+            rotation,
+            timezone: Tz::UTC,
+            window_start: rotation.window_start(Utc::now(), Tz::UTC),
+            key_template: KeyTemplate::default(),
+            hostname: hostname::get()
+                .map(|hostname| hostname.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| "unknown".to_string()),
+            pid: std::process::id(),
+            service: None,
+            record_separator: "\n".to_string(),
         }
     }
 
-    /// Increments the part number and updates the file name.
+    /// Sets the time window after which a new file is started, and the timezone used for
+    /// the windows and the date in file names.
+    ///
+    /// # Arguments
+    /// * `rotation` - The rotation window
+    /// * `timezone` - The timezone windows are aligned to
+    pub fn with_rotation(mut self, rotation: Rotation, timezone: Tz) -> Self {
+        self.rotation = rotation;
+        self.timezone = timezone;
+        self.window_start = rotation.window_start(Utc::now(), timezone);
+        self
+    }
+
+    /// Starts the current rotation window at the one containing `now` instead of the
+    /// current time.
+    ///
+    /// # Arguments
+    /// * `now` - The time the first window contains
+    pub fn with_window_start(mut self, now: DateTime<Utc>) -> Self {
+        self.window_start = self.rotation.window_start(now, self.timezone);
+        self
+    }
+
+    /// Sets the layout of file names.
+    ///
+    /// # Arguments
+    /// * `key_template` - The file name template
+    /// * `service` - The value of the `{service}` placeholder
+    pub fn with_key_template(mut self, key_template: KeyTemplate, service: Option<String>) -> Self {
+        self.key_template = key_template;
+        self.service = service;
+        self
+    }
+
+    /// Sets the string terminating every log entry, a newline by default.
+    ///
+    /// # Arguments
+    /// * `record_separator` - The record separator
+    pub fn with_record_separator(mut self, record_separator: &str) -> Self {
+        self.record_separator = record_separator.to_string();
+        self
+    }
+
+    /// Starts a new file if `now` falls outside of the current rotation window.
+    ///
+    /// Entries still in the buffer keep the file name of the window they were appended in.
+    ///
+    /// # Arguments
+    /// * `now` - The current time
+    ///
+    /// # Returns
+    /// `true` if the file was rotated
+    pub async fn rotate_if_due(&mut self, now: DateTime<Utc>) -> bool {
+        let window_start = self.rotation.window_start(now, self.timezone);
+        if window_start == self.window_start {
+            return false;
+        }
+        {
+            let mut buffer = self.buffer.write().await;
+            for (destination, _) in buffer.entries.iter_mut() {
+                if let Destination::Current(level) = destination {
+                    *destination = Destination::Key(self.name_for(*level));
+                }
+            }
+        }
+        let previous = self.take_written_names();
+        self.window_start = window_start;
+        self.part.fetch_add(1, Ordering::Relaxed);
+        previous
+            .into_iter()
+            .for_each(|name| self.mark_rotated(name));
+        true
+    }
+
+    /// Increments the part number, which changes the file name.
     /// Called when the current log file becomes too large and needs to be split.
+    /// The previous file names that were written to are kept until they are finalized, see
+    /// [`Output::take_rotated`].
     pub fn bump_part(&mut self) {
+        let previous = self.take_written_names();
         self.part.fetch_add(1, Ordering::Relaxed);
-        let name = Self::gen_name(&self.prefix, self.part(), &self.postfix, &self.nonce);
-        self.update_name(&name);
+        previous
+            .into_iter()
+            .for_each(|name| self.mark_rotated(name));
     }
 
-    /// Generates a log file name based on the current date, part number, and configuration.
+    /// Returns the current file names that received an entry, and forgets them.
+    fn take_written_names(&mut self) -> Vec<String> {
+        let names = self.written_names();
+        self.written.get_mut().unwrap().clear();
+        names
+    }
+
+    /// Returns the distinct current file names that received an entry, the only current
+    /// objects that need to be finalized.
+    pub fn written_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for level in self.written.lock().unwrap().iter() {
+            let name = self.name_for(*level);
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Records a file name that is no longer written to and needs to be finalized.
     ///
     /// # Arguments
-    /// * `prefix` - The file name prefix
-    /// * `part` - The part number for file splitting
-    /// * `postfix` - The file extension/postfix
-    /// * `nonce` - A unique identifier for this logging session
-    ///
-    /// # Returns
-    /// A formatted file name in the pattern: YYYY-MM-DD/part/prefix-nonce.postfix
-    pub fn gen_name(prefix: &str, part: u64, postfix: &str, nonce: &str) -> String {
-        let today = Local::now().date_naive();
-        format!(
-            "{}/{part}/{prefix}-{nonce}.{postfix}",
-            today.format("%Y-%m-%d"),
-        )
+    /// * `name` - The file name to finalize
+    pub fn mark_rotated(&mut self, name: String) {
+        if !self.names().contains(&name) && !self.rotated.contains(&name) {
+            self.rotated.push(name);
+        }
+    }
+
+    /// Takes the file names that were rotated away from and still need to be finalized.
+    pub fn take_rotated(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.rotated)
     }
 
     /// Returns the number of log entries currently in the buffer.
     pub async fn buffer_len(&self) -> u64 {
-        self.buffer.read().await.len() as u64
+        self.buffer.read().await.entries.len() as u64
     }
 
-    /// Flushes the buffer and returns all buffered log entries as a single string.
-    /// Clears the buffer and resets the size counter after flushing.
+    /// Flushes the buffer and returns the buffered log entries grouped by file name,
+    /// in the order the file names first appear.
+    ///
+    /// The buffer is swapped for an empty one in a single step under the write lock, so every
+    /// entry is either part of the returned batches or still in the buffer: entries appended
+    /// concurrently are never lost or returned twice, and the size counter always matches the
+    /// entries left in the buffer.
+    ///
+    /// Every entry is terminated by the record separator, so batches appended one after the
+    /// other to the same file never merge two entries into one line.
     ///
     /// # Returns
-    /// A newline-delimited string containing all buffered log entries
-    pub async fn flush_buffer(&self) -> String {
-        let payload = self.buffer.read().await.join("\n");
-        self.buffer.write().await.clear();
-        self.update_size_in_bytes(0);
-        payload
+    /// One batch of log entries per file name
+    pub async fn flush_buffer(&self) -> Vec<FlushedBatch> {
+        let flushed = {
+            let mut buffer = self.buffer.write().await;
+            let flushed = std::mem::take(&mut *buffer);
+            self.size_in_bytes.store(0, Ordering::Relaxed);
+            flushed
+        };
+        let mut batches: Vec<(FlushedBatch, Vec<String>)> = Vec::new();
+        for (destination, entry) in flushed.entries {
+            let key = match destination {
+                Destination::Current(level) => self.name_for(level),
+                Destination::Key(key) => key,
+            };
+            match batches.iter_mut().find(|(batch, _)| batch.key == key) {
+                Some((_, lines)) => lines.push(entry),
+                None => batches.push((
+                    FlushedBatch {
+                        key,
+                        payload: String::new(),
+                    },
+                    vec![entry],
+                )),
+            }
+        }
+        batches
+            .into_iter()
+            .map(|(batch, lines)| FlushedBatch {
+                payload: lines.concat(),
+                ..batch
+            })
+            .collect()
     }
 
-    /// Appends a log entry to the buffer and updates the size counter.
+    /// Appends a log entry, terminated by the record separator, to the buffer and updates
+    /// the size counter.
     ///
     /// # Arguments
+    /// * `level` - The level of the log entry, used by the `{level}` key placeholder
     /// * `value` - The log entry to append to the buffer
-    pub async fn append_to_buffer(&self, value: String) {
-        let size_in_kb = self
-            .size_in_bytes
-            .fetch_add(value.len() as u64, Ordering::Relaxed);
-        let mut mut_buffer = self.buffer.write().await;
-        mut_buffer.push(value);
-        self.update_size_in_bytes(size_in_kb);
+    pub async fn append_to_buffer(&self, level: Level, mut value: String) {
+        value.push_str(&self.record_separator);
+        {
+            let mut written = self.written.lock().unwrap();
+            if !written.contains(&level) {
+                written.push(level);
+            }
+        }
+        let mut buffer = self.buffer.write().await;
+        buffer.size_in_bytes += value.len() as u64;
+        buffer.entries.push((Destination::Current(level), value));
+        self.size_in_bytes
+            .store(buffer.size_in_bytes, Ordering::Relaxed);
+    }
+
+    /// Puts a payload that failed to upload back at the front of the buffer,
+    /// so it is sent again (ahead of newer entries) on the next flush.
+    /// The payload keeps its object key, even if the output rotated in the meantime.
+    ///
+    /// # Arguments
+    /// * `batch` - A batch previously returned by [`Output::flush_buffer`]
+    pub async fn requeue(&self, batch: FlushedBatch) {
+        let mut buffer = self.buffer.write().await;
+        buffer.size_in_bytes += batch.payload.len() as u64;
+        buffer
+            .entries
+            .insert(0, (Destination::Key(batch.key), batch.payload));
+        self.size_in_bytes
+            .store(buffer.size_in_bytes, Ordering::Relaxed);
     }
 
     /// Returns the current log file name.
+    /// With a `{level}` key template this is the file name of INFO records.
     pub fn name(&self) -> String {
-        self.name.clone()
+        self.name_for(Level::INFO)
+    }
+
+    /// Returns the current log file name for records of the given level.
+    ///
+    /// # Arguments
+    /// * `level` - The level of the records
+    pub fn name_for(&self, level: Level) -> String {
+        self.key_template.render(&KeyContext {
+            prefix: &self.prefix,
+            postfix: &self.postfix,
+            nonce: &self.nonce,
+            part: self.part(),
+            window_start: self.window_start,
+            hostname: &self.hostname,
+            pid: self.pid,
+            service: self.service.as_deref(),
+            level,
+        })
+    }
+
+    /// Returns the distinct current log file names across all levels.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for level in [
+            Level::TRACE,
+            Level::DEBUG,
+            Level::INFO,
+            Level::WARN,
+            Level::ERROR,
+        ] {
+            let name = self.name_for(level);
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
     }
 
     /// Returns the current buffer size in bytes.
@@ -114,105 +364,548 @@ impl Output {
     pub fn part(&self) -> u64 {
         self.part.load(Ordering::Relaxed)
     }
+}
 
-    /// Updates the buffer size counter.
-    ///
-    /// # Arguments
-    /// * `size_in_bytes` - The new buffer size in bytes
-    pub fn update_size_in_bytes(&self, size_in_bytes: u64) {
-        self.size_in_bytes.store(size_in_bytes, Ordering::Relaxed);
-    }
-
-    /// Updates the current log file name.
-    ///
-    /// # Arguments
-    /// * `name` - The new file name
-    pub fn update_name(&mut self, name: &str) {
-        self.name = name.to_string();
-    }
+/// Commands sent to the background worker alongside the event stream.
+pub enum WorkerCommand {
+    /// Drain pending events, upload everything that is buffered and keep running. With
+    /// `finalize` set the current objects are finalized too, e.g. a multipart upload is
+    /// completed so its content becomes visible.
+    Flush { reply: Reply, finalize: bool },
+    /// Drain pending events, upload everything that is buffered and stop the worker.
+    Shutdown(Reply),
 }
 
 /// The main tracing layer that handles log collection and S3 uploading.
 /// Implements the tracing-subscriber Layer trait to integrate with the tracing ecosystem.
-pub struct HttpLogLayer {
+///
+/// Uploads go through a [`LogSink`], S3 ([`S3Sink`]) by default.
+pub struct HttpLogLayer<K: LogSink = S3Sink> {
     pub output: Arc<RwLock<Output>>,
     pub config: Arc<TracingS3Config>,
-    pub event_tx: UnboundedSender<Value>,
+    pub events: Arc<EventQueue>,
+    pub sink: Arc<K>,
+    /// The subscriber the layer is registered with.
+    pub(crate) dispatch: OnceLock<WeakDispatch>,
+    flush_handle: FlushHandle,
 }
 
 impl HttpLogLayer {
-    /// Creates a background task that periodically flushes buffered logs to S3.
+    /// Creates a new HttpLogLayer instance uploading to S3.
+    ///
+    /// Spawns the background worker for event processing and periodic log flushing, on the
+    /// runtime selected with
+    /// [`TracingS3ConfigBuilder::worker_runtime`](crate::config::builder::TracingS3ConfigBuilder::worker_runtime).
+    /// The returned [`ShutdownGuard`] must be kept alive for as long as logs should be
+    /// shipped; dropping it (or calling [`ShutdownGuard::shutdown`]) flushes the remaining
+    /// events and stops the worker.
+    ///
+    /// # Arguments
+    /// * `config` - The S3 configuration wrapped in an Arc
+    ///
+    /// # Returns
+    /// * `Ok((HttpLogLayer, ShutdownGuard))` - A new HttpLogLayer instance ready to receive
+    ///   tracing events, and its shutdown guard
+    /// * `Err(TracingS3Error)` - If the background worker cannot be spawned, e.g. with
+    ///   [`WorkerRuntime::Ambient`](crate::config::worker_runtime::WorkerRuntime::Ambient)
+    ///   outside of a Tokio runtime
+    pub fn new(config: Arc<TracingS3Config>) -> Result<(Self, ShutdownGuard)> {
+        let sink = S3Sink::new(
+            config.aws_client.clone(),
+            &config.bucket,
+            config.retry_policy.clone(),
+        )
+        .with_strategy(config.upload_strategy)
+        .with_content_encoding(config.compression.content_encoding());
+        Self::with_sink(config, sink)
+    }
+}
+
+impl<K: LogSink> HttpLogLayer<K> {
+    /// Creates the background task that receives events and periodically flushes them to S3.
+    /// The task runs on the runtime selected by `worker_runtime`.
+    ///
+    /// The worker is the only consumer of the event queue, so appends and uploads never race
+    /// each other. Buffered events are uploaded on every tick, or as soon as the buffer grows
+    /// beyond `buffer_size_limit_kb`. With `flush_on_level`, an event at that level or more
+    /// severe triggers an upload as well, at most once per `flush_debounce_in_ms`. After a
+    /// failed upload the worker waits for the next tick before trying again. Every event and
+    /// tick starts a new object once the rotation window changed; events buffered before
+    /// that are still uploaded to the previous window's object, which is finalized once they
+    /// are. [`WorkerCommand::Flush`] drains the event queue and uploads right
+    /// away. The worker stops after handling [`WorkerCommand::Shutdown`] (or once the command
+    /// channel is closed), draining the event queue and running a final upload first.
+    ///
+    /// The worker runs inside [`reentrancy_guard::scope`], so events raised while uploading
+    /// are not fed back into the layer.
     ///
     /// # Arguments
     /// * `config` - The S3 configuration
     /// * `output` - The shared output buffer
+    /// * `events` - The queue of events emitted by the layer
+    /// * `sink` - The sink logs are uploaded to
+    /// * `command_rx` - The receiving half of the command channel
     ///
     /// # Returns
-    /// A JoinHandle for the background cron job task
-    pub fn cron_job(config: Arc<TracingS3Config>, output: Arc<RwLock<Output>>) -> JoinHandle<()> {
-        let buffer_size_limit_kb = config.buffer_size_limit_kb;
-        tokio::spawn(async move {
+    /// * `Ok(JoinHandle<()>)` - A JoinHandle for the background worker task
+    /// * `Err(TracingS3Error)` - If the worker cannot be spawned, see
+    ///   [`WorkerRuntime::spawn`](crate::config::worker_runtime::WorkerRuntime::spawn)
+    pub fn cron_job(
+        config: Arc<TracingS3Config>,
+        output: Arc<RwLock<Output>>,
+        events: Arc<EventQueue>,
+        sink: Arc<K>,
+        mut command_rx: UnboundedReceiver<WorkerCommand>,
+    ) -> Result<JoinHandle<()>> {
+        let buffer_size_limit_in_bytes = config.buffer_size_limit_kb * 1_024;
+        let worker_runtime = config.worker_runtime.clone();
+        worker_runtime.spawn(reentrancy_guard::scope(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_millis(config.cron_interval_in_ms));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval.tick().await;
+            let flush_debounce = Duration::from_millis(config.flush_debounce_in_ms);
+            let mut last_upload_failed = false;
+            let mut last_level_flush: Option<Instant> = None;
+            let mut level_flush_at: Option<Instant> = None;
             loop {
-                tokio::time::sleep(Duration::from_millis(config.cron_interval_in_ms)).await;
-                let buffer_len = output.read().await.buffer_len().await;
-                let size_in_bytes = output.read().await.size_in_bytes();
-                if buffer_len > 0 || size_in_bytes * 1_024 >= buffer_size_limit_kb {
-                    let _ = HttpLogLayer::send_logs(config.clone(), output.clone()).await;
+                tokio::select! {
+                    Some(value) = events.recv() => {
+                        let level = Self::append_event(&output, &events, value).await;
+                        if level_flush_at.is_none()
+                            && config.flush_on_level.is_some_and(|flush_on| level <= flush_on)
+                        {
+                            let now = Instant::now();
+                            level_flush_at = Some(
+                                last_level_flush.map_or(now, |last| (last + flush_debounce).max(now)),
+                            );
+                        }
+                        let size_in_bytes = output.read().await.size_in_bytes();
+                        if !last_upload_failed && size_in_bytes >= buffer_size_limit_in_bytes {
+                            last_upload_failed =
+                                Self::send_logs(config.clone(), output.clone(), &sink).await.is_err();
+                        }
+                    }
+                    _ = tokio::time::sleep_until(level_flush_at.unwrap_or_else(Instant::now)),
+                        if level_flush_at.is_some() =>
+                    {
+                        level_flush_at = None;
+                        last_level_flush = Some(Instant::now());
+                        if Self::has_pending(&config, &output).await {
+                            last_upload_failed =
+                                Self::send_logs(config.clone(), output.clone(), &sink).await.is_err();
+                        }
+                    }
+                    command = command_rx.recv() => {
+                        let finalize_current = match &command {
+                            Some(WorkerCommand::Flush { finalize, .. }) => *finalize,
+                            _ => true,
+                        };
+                        let result =
+                            Self::flush_all(&config, &output, &events, &sink, finalize_current).await;
+                        match command {
+                            Some(WorkerCommand::Flush { reply, .. }) => {
+                                last_upload_failed = result.is_err();
+                                reply.send(result);
+                            }
+                            Some(WorkerCommand::Shutdown(reply)) => {
+                                events.close();
+                                reply.send(result);
+                                return;
+                            }
+                            None => {
+                                events.close();
+                                return;
+                            }
+                        }
+                    }
+                    _ = interval.tick() => {
+                        Self::append_dropped_record(&output, events.take_dropped()).await;
+                        if Self::has_pending(&config, &output).await {
+                            last_upload_failed =
+                                Self::send_logs(config.clone(), output.clone(), &sink).await.is_err();
+                        }
+                        Self::rotate_if_due(&output, &sink).await;
+                    }
                 }
             }
-        })
+        }))
     }
 
-    /// Creates a new HttpLogLayer instance.
+    /// Serializes an event and appends it to the output buffer, first starting a new object
+    /// if the rotation window changed, so the event is not written to the previous one.
     ///
-    /// Sets up the background cron job for periodic log flushing and initializes
-    /// the event processing pipeline.
+    /// # Returns
+    /// The level of the event
+    async fn append_event(
+        output: &Arc<RwLock<Output>>,
+        events: &EventQueue,
+        value: Value,
+    ) -> Level {
+        output.write().await.rotate_if_due(Utc::now()).await;
+        Self::append_dropped_record(output, events.take_dropped()).await;
+        let level = value
+            .get("level")
+            .and_then(Value::as_str)
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(Level::INFO);
+        if let Ok(v) = serde_json::to_string(&value) {
+            output.read().await.append_to_buffer(level, v).await;
+        }
+        level
+    }
+
+    /// Appends a synthetic "N events dropped" record if events were dropped, because the
+    /// event queue overflowed or spooled batches were evicted, so the gap is visible in the
+    /// uploaded logs.
+    async fn append_dropped_record(output: &Arc<RwLock<Output>>, dropped: u64) {
+        if dropped == 0 {
+            return;
+        }
+        let event = json!({
+            "message": format!("{dropped} events dropped"),
+            "dropped": dropped,
+        });
+        Self::append_warning_record(output, event).await;
+    }
+
+    /// Appends a synthetic WARN record about a problem of the layer itself, e.g. a spool
+    /// segment that could not be read, as the worker cannot emit tracing events.
     ///
     /// # Arguments
-    /// * `config` - The S3 configuration wrapped in an Arc
+    /// * `output` - The output to append to
+    /// * `event` - The fields of the record, including its `message`
+    async fn append_warning_record(output: &Arc<RwLock<Output>>, mut event: Value) {
+        event["metadata"] = json!({
+            "target": "tracing_s3",
+            "level": "WARN",
+        });
+        let record = json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "level": "WARN",
+            "event": event,
+        });
+        output
+            .read()
+            .await
+            .append_to_buffer(Level::WARN, record.to_string())
+            .await;
+    }
+
+    /// Appends every event still queued, uploads whatever is buffered and finalizes the
+    /// objects rotated away from, plus the current ones that were written to if
+    /// `finalize_current` is set (on shutdown). A flush leaves the current objects open, so a multipart upload keeps going
+    /// instead of being completed and started over on every flush.
+    async fn flush_all(
+        config: &Arc<TracingS3Config>,
+        output: &Arc<RwLock<Output>>,
+        events: &EventQueue,
+        sink: &K,
+        finalize_current: bool,
+    ) -> Result<()> {
+        while let Some(value) = events.pop() {
+            Self::append_event(output, events, value).await;
+        }
+        Self::append_dropped_record(output, events.take_dropped()).await;
+        let mut result = if Self::has_pending(config, output).await {
+            Self::send_logs(config.clone(), output.clone(), sink).await
+        } else {
+            Ok(())
+        };
+        let mut keys = output.write().await.take_rotated();
+        if finalize_current {
+            keys.extend(output.read().await.written_names());
+        }
+        for key in keys {
+            if let Err(err) = sink.finalize(&key).await
+                && result.is_ok()
+            {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    /// Creates a new HttpLogLayer instance uploading to the given sink.
+    ///
+    /// Behaves like [`HttpLogLayer::new`], except that logs are written to `sink` instead
+    /// of S3. The S3 client and bucket in `config` are not used.
+    ///
+    /// # Arguments
+    /// * `config` - The layer configuration wrapped in an Arc
+    /// * `sink` - The sink logs are uploaded to
     ///
     /// # Returns
-    /// A new HttpLogLayer instance ready to receive tracing events
-    pub fn new(config: Arc<TracingS3Config>) -> Self {
-        let output = Arc::new(RwLock::new(Output::new(&config.prefix, &config.postfix)));
-        let (event_tx, mut event_rx): (UnboundedSender<Value>, UnboundedReceiver<Value>) =
-            mpsc::unbounded_channel();
-        let output_clone = output.clone();
-        tokio::spawn(async move {
-            while let Some(value) = event_rx.recv().await {
-                if let Ok(v) = serde_json::to_string(&value) {
-                    output_clone.read().await.append_to_buffer(v).await;
-                }
-            }
+    /// * `Ok((HttpLogLayer, ShutdownGuard))` - A new HttpLogLayer instance ready to receive
+    ///   tracing events, and its shutdown guard
+    /// * `Err(TracingS3Error)` - If the background worker cannot be spawned, e.g. with
+    ///   [`WorkerRuntime::Ambient`](crate::config::worker_runtime::WorkerRuntime::Ambient)
+    ///   outside of a Tokio runtime
+    pub fn with_sink(config: Arc<TracingS3Config>, sink: K) -> Result<(Self, ShutdownGuard)> {
+        let sink = Arc::new(sink);
+        let output = Arc::new(RwLock::new(
+            Output::new(
+                &config.prefix,
+                &format!("{}{}", config.postfix, config.compression.extension()),
+            )
+            .with_rotation(config.rotation, config.timezone)
+            .with_key_template(config.key_template.clone(), config.service.clone())
+            .with_record_separator(&config.record_separator),
+        ));
+        let events = Arc::new(match config.channel_capacity {
+            Some(capacity) => EventQueue::bounded(capacity, config.overflow_policy),
+            None => EventQueue::unbounded(),
         });
-        Self::cron_job(config.clone(), output.clone());
-        Self {
-            output,
-            config,
-            event_tx,
-        }
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let flush_handle = FlushHandle::new(command_tx.clone());
+        let worker = Self::cron_job(
+            config.clone(),
+            output.clone(),
+            events.clone(),
+            sink.clone(),
+            command_rx,
+        )?;
+        let guard = ShutdownGuard::new(
+            command_tx,
+            worker,
+            Duration::from_millis(config.shutdown_timeout_in_ms),
+        );
+        Ok((
+            Self {
+                output,
+                config,
+                events,
+                sink,
+                dispatch: OnceLock::new(),
+                flush_handle,
+            },
+            guard,
+        ))
+    }
+
+    /// Returns a handle to upload everything emitted so far on demand, see [`FlushHandle`].
+    /// Take it before the layer is moved into the subscriber.
+    pub fn flush_handle(&self) -> FlushHandle {
+        self.flush_handle.clone()
     }
 
-    /// Sends buffered logs to S3 and handles file partitioning if necessary.
+    /// Returns the total number of events dropped because the event channel was full.
+    pub fn dropped_events(&self) -> u64 {
+        self.events.dropped_total()
+    }
+
+    /// Returns true if there is anything to upload, in memory or in the disk spool.
+    async fn has_pending(config: &TracingS3Config, output: &Arc<RwLock<Output>>) -> bool {
+        output.read().await.buffer_len().await > 0
+            || config
+                .spool
+                .as_ref()
+                .is_some_and(|spool| !spool.lock().unwrap().is_empty())
+    }
+
+    /// Sends buffered logs to the sink and handles file partitioning if necessary.
+    ///
+    /// Once the object grows to `object_size_limit_mb` or beyond, it is finalized and the
+    /// following uploads go to the next part (see [`Output::bump_part`]).
+    ///
+    /// If the append fails with a transient error (after the sink's own retries, see
+    /// [`RetryPolicy`](crate::config::retry_policy::RetryPolicy) for S3), the payload is written
+    /// to the disk spool when one is configured, or put back into the buffer otherwise (or if
+    /// it cannot be spooled), so it is not lost. Spooled batches are replayed before any new
+    /// data is sent, keeping uploads in order. Objects other than the current ones, e.g. those
+    /// of requeued batches, are finalized again once written.
     ///
     /// # Arguments
     /// * `config` - The S3 configuration
     /// * `output` - The shared output buffer
+    /// * `sink` - The sink logs are uploaded to
     ///
     /// # Returns
     /// * `Ok(())` - If logs were successfully sent
-    /// * `Err(anyhow::Error)` - If the S3 upload operation fails
+    /// * `Err(TracingS3Error)` - If the upload operation fails
     pub async fn send_logs(
         config: Arc<TracingS3Config>,
         output: Arc<RwLock<Output>>,
-    ) -> anyhow::Result<()> {
-        let payload = output.read().await.flush_buffer().await;
-        let name = output.read().await.name();
-        let total_size =
-            S3Helpers::append_to_file(&config.aws_client, &config.bucket, &name, &payload).await?;
-        if total_size > config.buffer_size_limit_kb * 1_024 {
+        sink: &K,
+    ) -> Result<()> {
+        let batches = output.read().await.flush_buffer().await;
+        // Objects that still get a batch are finalized once it is uploaded.
+        let pending: Vec<&str> = batches.iter().map(|batch| batch.key.as_str()).collect();
+        Self::finalize_rotated_except(&output, sink, &pending).await;
+        if let Some(spool) = &config.spool
+            && let Err(err) = Self::replay_spool(spool, config.compression, &output, sink).await
+        {
+            Self::keep_failed(&config, &output, batches).await;
+            return Err(err);
+        }
+        let object_size_limit_in_bytes = config.object_size_limit_mb * 1_024 * 1_024;
+        let mut result = Ok(());
+        let mut rotate = false;
+        let mut batches = batches.into_iter();
+        while let Some(batch) = batches.next() {
+            match Self::upload(config.compression, sink, &batch.key, &batch.payload).await {
+                Ok(total_size) => {
+                    rotate |= total_size >= object_size_limit_in_bytes;
+                    output.write().await.mark_rotated(batch.key);
+                }
+                Err(err) if sink.is_retryable(&err) => {
+                    let failed = std::iter::once(batch).chain(batches).collect();
+                    Self::keep_failed(&config, &output, failed).await;
+                    result = Err(err);
+                    break;
+                }
+                Err(err) => {
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                }
+            }
+        }
+        if rotate {
             output.write().await.bump_part();
         }
-        Ok(())
+        Self::finalize_rotated(&output, sink).await;
+        result
+    }
+
+    /// Keeps batches that failed to upload: writes them to the disk spool when one is
+    /// configured, and puts the ones that could not be spooled back into the buffer.
+    /// Batches evicted from the spool to make room are replaced by an "N events dropped"
+    /// record, and failures after a batch was spooled by a warning record.
+    async fn keep_failed(
+        config: &TracingS3Config,
+        output: &Arc<RwLock<Output>>,
+        mut batches: Vec<FlushedBatch>,
+    ) {
+        if let Some(spool) = &config.spool {
+            let (unspooled, pushed) = Self::with_spool(spool, move |spool| {
+                let mut batches = batches.into_iter();
+                let mut unspooled = Vec::new();
+                let mut pushed = Pushed::default();
+                for batch in batches.by_ref() {
+                    match spool.push(&batch.key, &batch.payload) {
+                        Ok(batch_pushed) => {
+                            pushed.evicted.extend(batch_pushed.evicted);
+                            pushed.errors.extend(batch_pushed.errors);
+                        }
+                        Err(_) => {
+                            unspooled.push(batch);
+                            break;
+                        }
+                    }
+                }
+                unspooled.extend(batches);
+                Ok((unspooled, pushed))
+            })
+            .await
+            .unwrap_or_default();
+            let dropped = pushed
+                .evicted
+                .iter()
+                .map(|batch| batch.payload.matches(&config.record_separator).count() as u64)
+                .sum();
+            Self::append_dropped_record(output, dropped).await;
+            for err in pushed.errors {
+                let message = format!("Spool maintenance failed: {err}");
+                Self::append_warning_record(output, json!({ "message": message })).await;
+            }
+            batches = unspooled;
+        }
+        for batch in batches.into_iter().rev() {
+            output.read().await.requeue(batch).await;
+        }
+    }
+
+    /// Runs an operation on the disk spool on the blocking thread pool, as it does file I/O
+    /// while holding the spool lock.
+    async fn with_spool<T, F>(spool: &Arc<Mutex<Spool>>, operation: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Spool) -> Result<T> + Send + 'static,
+    {
+        let spool = spool.clone();
+        tokio::task::spawn_blocking(move || operation(&mut spool.lock().unwrap()))
+            .await
+            .map_err(|err| TracingS3Error::Worker(format!("Spool operation failed: {err}")))?
+    }
+
+    /// Compresses a payload into a self-contained frame and appends it to the object.
+    async fn upload(compression: Compression, sink: &K, key: &str, payload: &str) -> Result<u64> {
+        let body = compression.compress(payload.as_bytes())?;
+        sink.append(key, &body).await
+    }
+
+    /// Starts a new object once the rotation window changed, finalizing the previous one.
+    async fn rotate_if_due(output: &Arc<RwLock<Output>>, sink: &K) {
+        if output.write().await.rotate_if_due(Utc::now()).await {
+            Self::finalize_rotated(output, sink).await;
+        }
+    }
+
+    /// Finalizes the objects that were rotated away from, e.g. completes their multipart
+    /// uploads. Objects that fail to finalize are kept and retried on the next upload.
+    async fn finalize_rotated(output: &Arc<RwLock<Output>>, sink: &K) {
+        Self::finalize_rotated_except(output, sink, &[]).await;
+    }
+
+    /// Like [`HttpLogLayer::finalize_rotated`], keeping the objects in `pending` for later.
+    async fn finalize_rotated_except(output: &Arc<RwLock<Output>>, sink: &K, pending: &[&str]) {
+        let rotated = output.write().await.take_rotated();
+        for key in rotated {
+            if pending.contains(&key.as_str()) || sink.finalize(&key).await.is_err() {
+                output.write().await.mark_rotated(key);
+            }
+        }
+    }
+
+    /// Uploads spooled batches, oldest first, to the objects they were meant for.
+    ///
+    /// Stops at the first transient failure, leaving that batch and everything after it
+    /// in the spool. Batches that S3 rejects permanently are discarded so they cannot
+    /// block the rest of the spool forever, and so are segments that cannot be read back,
+    /// which are moved aside with a warning record. Objects other than the current one are
+    /// finalized again once their batches have been replayed.
+    async fn replay_spool(
+        spool: &Arc<Mutex<Spool>>,
+        compression: Compression,
+        output: &Arc<RwLock<Output>>,
+        sink: &K,
+    ) -> Result<()> {
+        loop {
+            let (batch, unreadable) = Self::with_spool(spool, |spool| {
+                let mut unreadable = Vec::new();
+                loop {
+                    match spool.front() {
+                        Ok(batch) => return Ok((batch, unreadable)),
+                        // Left by a crash or damaged on disk; it would block the segments
+                        // after it for good.
+                        Err(err) => unreadable.push(match spool.quarantine_front() {
+                            Ok(Some(path)) => format!(
+                                "Unreadable spool segment moved to {}: {err}",
+                                path.display()
+                            ),
+                            Ok(None) => format!("Spool segment disappeared: {err}"),
+                            Err(quarantine_err) => format!(
+                                "Unreadable spool segment skipped: {err}; it could not be \
+                                 moved aside: {quarantine_err}"
+                            ),
+                        }),
+                    }
+                }
+            })
+            .await?;
+            for message in unreadable {
+                Self::append_warning_record(output, json!({ "message": message })).await;
+            }
+            let Some(batch) = batch else {
+                Self::finalize_rotated(output, sink).await;
+                return Ok(());
+            };
+            match Self::upload(compression, sink, &batch.key, &batch.payload).await {
+                Err(err) if sink.is_retryable(&err) => return Err(err),
+                _ => Self::with_spool(spool, Spool::pop_front).await?,
+            }
+            output.write().await.mark_rotated(batch.key);
+        }
     }
 }
@@ -1,15 +1,21 @@
 use crate::layer::http_log_layer::HttpLogLayer;
+use crate::layer::reentrancy_guard;
+use crate::layer::span_fields::{self, SpanFields};
+use crate::layer::trace_context::{self, FallbackTraceId};
+use crate::sink::LogSink;
 use crate::with_event_from_span;
 use serde_json::json;
 use tokio::time::Instant;
-use tracing::span::Attributes;
-use tracing::{Event, Id, Subscriber, field};
+use tracing::dispatcher::WeakDispatch;
+use tracing::span::{Attributes, Record};
+use tracing::{Dispatch, Event, Id, Subscriber, field};
 use tracing_serde::AsSerde;
 use tracing_subscriber::Layer;
+use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::layer::Context;
 
 /// Structure to track timing information for spans.
-/// Tracks idle and busy time for performance monitoring.
+/// Tracks idle and busy time for performance monitoring, in nanoseconds.
 pub struct Timings {
     idle: u64,
     busy: u64,
@@ -27,39 +33,97 @@ impl Timings {
     }
 }
 
-impl<S> Layer<S> for HttpLogLayer
+impl<K: LogSink> HttpLogLayer<K> {
+    /// Returns whether the configured span events include `kind`.
+    fn emits(&self, kind: FmtSpan) -> bool {
+        self.config.span_events.clone() & kind.clone() == kind
+    }
+}
+
+impl<S, K> Layer<S> for HttpLogLayer<K>
 where
+    K: LogSink,
     S: Subscriber
         + for<'span> tracing_subscriber::registry::LookupSpan<'span>
         + for<'lookup> tracing_subscriber::registry::LookupSpan<'lookup>,
     Self: 'static,
 {
-    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
         let span = ctx.span(id).expect("Span not found, this is a bug");
         let mut extensions = span.extensions_mut();
 
         if extensions.get_mut::<Timings>().is_none() {
             extensions.insert(Timings::new());
         }
+        if (self.config.current_span || self.config.span_list)
+            && extensions.get_mut::<SpanFields>().is_none()
+        {
+            extensions.insert(SpanFields::new(attrs));
+        }
+        if self.config.trace_context && span.parent().is_none() {
+            extensions.insert(FallbackTraceId::new());
+        }
 
-        with_event_from_span!(id, span, "message" = "new", |_event| {
+        if !self.emits(FmtSpan::NEW) {
+            return;
+        }
+        with_event_from_span!(id, span, "message" = "new", |event| {
             drop(extensions);
             drop(span);
-            // self.on_event(&event, ctx);
+            self.on_event(&event, ctx);
         });
     }
 
-    fn on_event(&self, event: &Event, _ctx: Context<S>) {
-        let log = json!({
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        let _ = self.dispatch.set(subscriber.downgrade());
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found, this is a bug");
+        if let Some(fields) = span.extensions_mut().get_mut::<SpanFields>() {
+            fields.record(values);
+        }
+    }
+
+    fn on_event(&self, event: &Event, ctx: Context<S>) {
+        if reentrancy_guard::is_inside_worker()
+            || reentrancy_guard::is_denied(event.metadata().target(), &self.config.denied_targets)
+        {
+            return;
+        }
+        let mut log = json!({
             "timestamp": chrono::Utc::now().to_rfc3339(),
             "level": event.metadata().level().to_string(),
             "event": event.as_serde(),
         });
-        let _ = self.event_tx.send(log);
+        if let Some(span) = ctx.event_span(event) {
+            if self.config.trace_context {
+                let dispatch = self.dispatch.get().and_then(WeakDispatch::upgrade);
+                for (name, value) in trace_context::trace_context(&span, dispatch.as_ref()) {
+                    log[name] = value;
+                }
+            }
+            if self.config.current_span {
+                log["span"] = span_fields::span_to_json(&span);
+            }
+        }
+        if self.config.span_list
+            && let Some(scope) = ctx.event_scope(event)
+        {
+            log["spans"] = span_fields::span_list_to_json(scope);
+        }
+        self.events.push(log);
     }
 
     fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
         let span = ctx.span(id).expect("Span not found, this is a bug");
+        #[cfg(feature = "opentelemetry")]
+        if self.config.trace_context
+            && let Some(dispatch) = self.dispatch.get().and_then(WeakDispatch::upgrade)
+        {
+            // Resolve the IDs while tracing-opentelemetry still tracks the span.
+            trace_context::cache_trace_context(&span, &dispatch);
+        }
         let mut extensions = span.extensions_mut();
         let mut busy: Option<u64> = None;
         let mut idle: Option<u64> = None;
@@ -71,16 +135,19 @@ where
             busy = Option::from(timings.busy);
             idle = Option::from(timings.idle);
         }
+        if !self.emits(FmtSpan::ENTER) {
+            return;
+        }
         with_event_from_span!(
             id,
             span,
             "message" = "enter",
             "time.busy" = busy,
             "time.idle" = idle,
-            |_event| {
+            |event| {
                 drop(extensions);
                 drop(span);
-                // self.on_event(&event, ctx);
+                self.on_event(&event, ctx);
             }
         );
     }
@@ -97,21 +164,27 @@ where
             busy = Option::from(timings.busy);
             idle = Option::from(timings.idle);
         }
+        if !self.emits(FmtSpan::EXIT) {
+            return;
+        }
         with_event_from_span!(
             id,
             span,
             "message" = "exit",
             "time.busy" = busy,
             "time.idle" = idle,
-            |_event| {
+            |event| {
                 drop(extensions);
                 drop(span);
-                // self.on_event(&event, ctx);
+                self.on_event(&event, ctx);
             }
         );
     }
 
     fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if !self.emits(FmtSpan::CLOSE) {
+            return;
+        }
         let span = ctx.span(&id).expect("Span not found, this is a bug");
         let extensions = span.extensions();
         if let Some(timing) = extensions.get::<Timings>() {
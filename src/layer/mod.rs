@@ -1,3 +1,10 @@
+pub mod event_queue;
+pub mod flush_handle;
 pub mod http_log_layer;
 pub mod http_log_layer_subscriber_trait;
+pub mod panic_hook;
+pub mod reentrancy_guard;
+pub mod shutdown_guard;
+pub mod span_fields;
+pub mod trace_context;
 pub mod with_event_from_span;
@@ -0,0 +1,94 @@
+use crate::layer::http_log_layer::HttpLogLayer;
+use crate::layer::reentrancy_guard;
+use crate::layer::span_fields;
+use crate::sink::LogSink;
+use serde_json::{Value, json};
+use std::backtrace::Backtrace;
+use std::panic::PanicHookInfo;
+use std::time::Duration;
+use tracing_subscriber::Registry;
+use tracing_subscriber::registry::LookupSpan;
+
+impl<K: LogSink> HttpLogLayer<K> {
+    /// Installs a panic hook that records panics and uploads them before the process goes down.
+    ///
+    /// On every panic the hook queues an ERROR record holding the panic message, its location,
+    /// the panicking thread, a backtrace and the span stack the panic happened in (see
+    /// [`panic_record`]). It then uploads it and finalizes the current objects synchronously,
+    /// waiting at most `timeout` (see
+    /// [`FlushHandle::finalize_blocking`](crate::layer::flush_handle::FlushHandle::finalize_blocking)),
+    /// so the record is stored in a visible object even if the process aborts or exits
+    /// without dropping the [`ShutdownGuard`](crate::layer::shutdown_guard::ShutdownGuard).
+    /// Finally it calls the hook that was installed before, so the usual panic message is
+    /// still printed.
+    ///
+    /// The flush needs the background worker to run on another thread than the panicking
+    /// one, so on a current-thread runtime it only gives up after `timeout`, unless the
+    /// worker has its own thread, see
+    /// [`WorkerRuntime::DedicatedThread`](crate::config::worker_runtime::WorkerRuntime::DedicatedThread).
+    /// Panics of the worker itself are passed on to the previous hook without being recorded,
+    /// and the record is dropped if a bounded event channel is full, whatever its
+    /// [`OverflowPolicy`](crate::layer::event_queue::OverflowPolicy).
+    ///
+    /// # Arguments
+    /// * `timeout` - The maximum time a panicking thread waits for the upload
+    pub fn install_panic_hook(&self, timeout: Duration) {
+        let events = self.events.clone();
+        let flush_handle = self.flush_handle();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !reentrancy_guard::is_inside_worker() {
+                // Blocking on a full queue here could hang the panicking thread for good.
+                events.try_push(panic_record(info));
+                let _ = flush_handle.finalize_blocking(timeout);
+            }
+            previous(info);
+        }));
+    }
+}
+
+/// Builds the record written for a panic.
+///
+/// The record has the layout of other events, with `message`, `location`, `thread` and
+/// `backtrace` fields, plus the span stack under `spans`, outermost span first. The
+/// backtrace is captured whatever `RUST_BACKTRACE` is set to.
+///
+/// # Arguments
+/// * `info` - The panic information passed to the panic hook
+pub fn panic_record(info: &PanicHookInfo<'_>) -> Value {
+    let payload = info.payload();
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string());
+    let mut record = json!({
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "level": "ERROR",
+        "event": {
+            "metadata": {
+                "target": "panic",
+                "level": "ERROR",
+            },
+            "message": message,
+            "location": info.location().map(|location| location.to_string()),
+            "thread": std::thread::current().name().unwrap_or("<unnamed>"),
+            "backtrace": Backtrace::force_capture().to_string(),
+        },
+    });
+    if let Some(spans) = current_span_stack() {
+        record["spans"] = spans;
+    }
+    record
+}
+
+/// Returns the span stack of the current thread, if its default subscriber is built on a
+/// [`Registry`].
+fn current_span_stack() -> Option<Value> {
+    let id = tracing::Span::current().id()?;
+    tracing::dispatcher::get_default(|dispatch| {
+        let registry = dispatch.downcast_ref::<Registry>()?;
+        let span = registry.span(&id)?;
+        Some(span_fields::span_list_to_json(span.scope()))
+    })
+}
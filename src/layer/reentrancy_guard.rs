@@ -0,0 +1,83 @@
+use std::future::Future;
+
+/// Targets of events that are never recorded by default: the AWS SDK and the HTTP client it
+/// uploads with. Entries ending in `_` match any target starting with them (`aws_` matches
+/// every AWS crate), other entries match the crate itself and its modules (`hyper` matches
+/// `hyper::proto` but neither `hyper_util` nor `hyperion`).
+pub const DEFAULT_DENIED_TARGETS: &[&str] = &["aws_", "hyper", "hyper_util", "h2"];
+
+tokio::task_local! {
+    static INSIDE_WORKER: ();
+}
+
+/// Runs a future as part of the background worker.
+///
+/// Events raised while the future is polled, e.g. by the AWS SDK during an upload, are not
+/// recorded by the layer, since recording them would produce more uploads and so more events.
+///
+/// # Arguments
+/// * `future` - The worker future
+pub async fn scope<F: Future>(future: F) -> F::Output {
+    INSIDE_WORKER.scope((), future).await
+}
+
+/// Returns true if called from within [`scope`], i.e. from the background worker.
+pub fn is_inside_worker() -> bool {
+    INSIDE_WORKER.try_with(|_| ()).is_ok()
+}
+
+/// Returns true if events with the given target are denied by one of the entries.
+///
+/// # Arguments
+/// * `target` - The event target
+/// * `denied_targets` - The deny-list, see [`DEFAULT_DENIED_TARGETS`] for the matching rules
+pub fn is_denied<T: AsRef<str>>(target: &str, denied_targets: &[T]) -> bool {
+    denied_targets.iter().any(|denied| {
+        let denied = denied.as_ref();
+        if denied.ends_with('_') {
+            return target.starts_with(denied);
+        }
+        match target.strip_prefix(denied) {
+            Some(rest) => rest.is_empty() || rest.starts_with("::"),
+            None => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::reentrancy_guard::{
+        DEFAULT_DENIED_TARGETS, is_denied, is_inside_worker, scope,
+    };
+
+    #[test]
+    fn denies_sdk_and_http_targets() {
+        for target in [
+            "aws_smithy_runtime::client::orchestrator",
+            "aws_sdk_s3",
+            "hyper",
+            "hyper_util::client::legacy",
+            "h2::proto::connection",
+        ] {
+            assert!(is_denied(target, DEFAULT_DENIED_TARGETS), "{target}");
+        }
+        for target in [
+            "my_app",
+            "h2o",
+            "hyperion::api",
+            "hyper_tls",
+            "awsome",
+            "tower_http::trace::on_response",
+            "rustls::client",
+        ] {
+            assert!(!is_denied(target, DEFAULT_DENIED_TARGETS), "{target}");
+        }
+    }
+
+    #[tokio::test]
+    async fn scope_marks_the_worker() {
+        assert!(!is_inside_worker());
+        assert!(scope(async { is_inside_worker() }).await);
+        assert!(!is_inside_worker());
+    }
+}
@@ -0,0 +1,151 @@
+use crate::error::{Result, TracingS3Error};
+use crate::layer::flush_handle::FlushHandle;
+use crate::layer::http_log_layer::WorkerCommand;
+use std::time::Duration;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// The channel a worker uses to report the outcome of a command back to its caller.
+///
+/// Async callers await a oneshot receiver, while blocking callers (such as `Drop`)
+/// wait on a standard library channel so they can use a timeout outside any runtime.
+pub enum Reply {
+    Async(oneshot::Sender<Result<()>>),
+    Blocking(std::sync::mpsc::Sender<Result<()>>),
+}
+
+impl Reply {
+    /// Sends the outcome to the waiting caller, ignoring callers that gave up already.
+    pub fn send(self, result: Result<()>) {
+        match self {
+            Reply::Async(tx) => {
+                let _ = tx.send(result);
+            }
+            Reply::Blocking(tx) => {
+                let _ = tx.send(result);
+            }
+        }
+    }
+
+    /// Blocks the current thread until a reply arrives or the timeout elapses.
+    ///
+    /// Inside a multi-threaded Tokio runtime the wait is wrapped in `block_in_place`
+    /// so other tasks (including the worker) keep making progress.
+    ///
+    /// # Arguments
+    /// * `rx` - The receiving half of a [`Reply::Blocking`] channel
+    /// * `timeout` - The maximum time to wait
+    pub fn wait_blocking(
+        rx: std::sync::mpsc::Receiver<Result<()>>,
+        timeout: Duration,
+    ) -> Result<()> {
+        let wait = || match rx.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(_) => Err(TracingS3Error::Worker(format!(
+                "Timed out after {timeout:?} waiting for the log worker"
+            ))),
+        };
+        match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(wait)
+            }
+            _ => wait(),
+        }
+    }
+}
+
+/// Guard returned together with [`HttpLogLayer`](crate::layer::http_log_layer::HttpLogLayer).
+///
+/// Dropping the guard, or calling [`ShutdownGuard::shutdown`], drains the pending events,
+/// uploads everything still buffered and stops the background worker, waiting at most the
+/// configured shutdown timeout. Keep it alive (e.g. bound to a variable in `main`) for as
+/// long as logs should be shipped; binding it to `_` shuts the worker down immediately.
+///
+/// Dropping the guard blocks the current thread. On a current-thread runtime the worker
+/// cannot make progress while the thread is blocked, so prefer `shutdown().await` there, or
+/// run the worker on its own thread with
+/// [`WorkerRuntime::DedicatedThread`](crate::config::worker_runtime::WorkerRuntime::DedicatedThread).
+pub struct ShutdownGuard {
+    command_tx: UnboundedSender<WorkerCommand>,
+    worker: Option<JoinHandle<()>>,
+    timeout: Duration,
+}
+
+impl ShutdownGuard {
+    /// Creates a new ShutdownGuard for a running worker.
+    ///
+    /// # Arguments
+    /// * `command_tx` - The worker's command channel
+    /// * `worker` - The worker's JoinHandle
+    /// * `timeout` - The maximum time to wait for the final upload
+    pub fn new(
+        command_tx: UnboundedSender<WorkerCommand>,
+        worker: JoinHandle<()>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            command_tx,
+            worker: Some(worker),
+            timeout,
+        }
+    }
+
+    /// Returns a handle to upload everything emitted so far without stopping the worker,
+    /// see [`FlushHandle`].
+    pub fn flush_handle(&self) -> FlushHandle {
+        FlushHandle::new(self.command_tx.clone())
+    }
+
+    /// Flushes all pending events to S3 and stops the background worker.
+    ///
+    /// # Returns
+    /// * `Ok(())` - If the final upload succeeded (or there was nothing to upload)
+    /// * `Err(TracingS3Error)` - If the final upload failed, the worker is gone, or the
+    ///   shutdown timeout elapsed
+    pub async fn shutdown(mut self) -> Result<()> {
+        let worker = self.worker.take();
+        let (tx, rx) = oneshot::channel();
+        self.command_tx
+            .send(WorkerCommand::Shutdown(Reply::Async(tx)))
+            .map_err(|_| {
+                TracingS3Error::Worker("The log worker is no longer running".to_string())
+            })?;
+        let result = match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(TracingS3Error::Worker(
+                "The log worker stopped before replying".to_string(),
+            )),
+            Err(_) => Err(TracingS3Error::Worker(format!(
+                "Timed out after {:?} waiting for the log worker",
+                self.timeout
+            ))),
+        };
+        if let Some(worker) = worker {
+            if result.is_err() {
+                worker.abort();
+            } else {
+                let _ = worker.await;
+            }
+        }
+        result
+    }
+}
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        let Some(worker) = self.worker.take() else {
+            return;
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        if self
+            .command_tx
+            .send(WorkerCommand::Shutdown(Reply::Blocking(tx)))
+            .is_ok()
+            && Reply::wait_blocking(rx, self.timeout).is_err()
+        {
+            worker.abort();
+        }
+    }
+}
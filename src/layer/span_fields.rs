@@ -0,0 +1,90 @@
+use serde_json::{Map, Value, json};
+use std::fmt::Debug;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Record};
+use tracing_subscriber::registry::{LookupSpan, Scope, SpanRef};
+
+/// The fields recorded on a span, stored in the span's extensions.
+///
+/// Filled from the span's attributes when it is created and updated whenever a value is
+/// added later with `Span::record`.
+#[derive(Debug, Default)]
+pub struct SpanFields(Map<String, Value>);
+
+impl SpanFields {
+    /// Creates the field set of a new span.
+    ///
+    /// # Arguments
+    /// * `attrs` - The attributes the span was created with
+    pub fn new(attrs: &Attributes<'_>) -> Self {
+        let mut fields = Self::default();
+        attrs.record(&mut fields);
+        fields
+    }
+
+    /// Adds or replaces the values recorded with `Span::record`.
+    ///
+    /// # Arguments
+    /// * `values` - The newly recorded values
+    pub fn record(&mut self, values: &Record<'_>) {
+        values.record(self);
+    }
+
+    fn insert(&mut self, field: &Field, value: Value) {
+        self.0.insert(field.name().to_string(), value);
+    }
+}
+
+impl Visit for SpanFields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, json!(value));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.insert(field, json!(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.insert(field, json!(format!("{value:?}")));
+    }
+}
+
+/// Serializes a span as its name plus its recorded fields, like the JSON formatter of
+/// `tracing_subscriber` does.
+///
+/// # Arguments
+/// * `span` - The span to serialize
+pub fn span_to_json<'a, R: LookupSpan<'a>>(span: &SpanRef<'a, R>) -> Value {
+    let mut object = Map::new();
+    object.insert("name".to_string(), json!(span.name()));
+    if let Some(fields) = span.extensions().get::<SpanFields>() {
+        for (name, value) in &fields.0 {
+            object.insert(name.clone(), value.clone());
+        }
+    }
+    Value::Object(object)
+}
+
+/// Serializes a span stack, outermost span first.
+///
+/// # Arguments
+/// * `scope` - The spans an event happened in, innermost first
+pub fn span_list_to_json<'a, R: LookupSpan<'a>>(scope: Scope<'a, R>) -> Value {
+    Value::Array(scope.from_root().map(|span| span_to_json(&span)).collect())
+}
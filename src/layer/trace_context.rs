@@ -0,0 +1,125 @@
+use serde_json::{Map, Value, json};
+use tracing::Dispatch;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+/// Trace correlation fields resolved from OpenTelemetry, cached in the span's extensions.
+///
+/// `tracing-opentelemetry` removes its data when a span closes, before this layer records
+/// the close event, so the IDs are kept from the last lookup.
+#[derive(Debug, Clone)]
+pub struct TraceIds(Map<String, Value>);
+
+/// Random trace ID of a root span, used when OpenTelemetry does not provide one.
+///
+/// Generated when the root span is created, as tracing reuses the IDs of closed spans and
+/// a root span ID would tie unrelated traces together.
+#[derive(Debug, Clone, Copy)]
+pub struct FallbackTraceId(u128);
+
+impl FallbackTraceId {
+    /// Generates a new random, non-zero trace ID.
+    pub fn new() -> Self {
+        Self(fastrand::u128(1..))
+    }
+}
+
+impl Default for FallbackTraceId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns the trace correlation fields of a span: `trace_id`, `span_id` and `trace_flags`.
+///
+/// With the `opentelemetry` feature and a `tracing-opentelemetry` layer in the subscriber,
+/// the IDs are the W3C trace context of the span's OpenTelemetry span (32 and 16 lowercase
+/// hex digits, flags as 2 hex digits). Otherwise the `trace_id` is the random
+/// [`FallbackTraceId`] of the root span, the `span_id` is tracing's own ID of the span, in
+/// the same hex layout, and `trace_flags` is `00` (not sampled).
+///
+/// # Arguments
+/// * `span` - The span an event happened in
+/// * `dispatch` - The subscriber the layer is registered with, used to reach the
+///   `tracing-opentelemetry` layer
+pub fn trace_context<'a, R: LookupSpan<'a>>(
+    span: &SpanRef<'a, R>,
+    dispatch: Option<&Dispatch>,
+) -> Map<String, Value> {
+    if let Some(TraceIds(fields)) = span.extensions().get::<TraceIds>() {
+        return fields.clone();
+    }
+    #[cfg(feature = "opentelemetry")]
+    if let Some(dispatch) = dispatch
+        && let Some(fields) = otel_trace_context(span, dispatch)
+    {
+        span.extensions_mut().insert(TraceIds(fields.clone()));
+        return fields;
+    }
+    #[cfg(not(feature = "opentelemetry"))]
+    let _ = dispatch;
+    let root = span.scope().from_root().next();
+    let root = root.as_ref().unwrap_or(span);
+    let existing = root.extensions().get::<FallbackTraceId>().copied();
+    // Spans created before the layer was registered have none yet.
+    let FallbackTraceId(trace_id) = existing.unwrap_or_else(|| {
+        let trace_id = FallbackTraceId::new();
+        root.extensions_mut().insert(trace_id);
+        trace_id
+    });
+    let mut fields = Map::new();
+    fields.insert("trace_id".to_string(), json!(format!("{trace_id:032x}")));
+    fields.insert(
+        "span_id".to_string(),
+        json!(format!("{:016x}", span.id().into_u64())),
+    );
+    fields.insert("trace_flags".to_string(), json!("00"));
+    fields
+}
+
+/// Caches the OpenTelemetry trace context of `span` as [`TraceIds`], if it is not cached
+/// yet, so it is still known once `tracing-opentelemetry` stops tracking the span. Spans
+/// without an OpenTelemetry context are left alone.
+///
+/// # Arguments
+/// * `span` - The span to cache the IDs of
+/// * `dispatch` - The subscriber the layer is registered with
+#[cfg(feature = "opentelemetry")]
+pub fn cache_trace_context<'a, R: LookupSpan<'a>>(span: &SpanRef<'a, R>, dispatch: &Dispatch) {
+    if span.extensions().get::<TraceIds>().is_some() {
+        return;
+    }
+    if let Some(fields) = otel_trace_context(span, dispatch) {
+        span.extensions_mut().insert(TraceIds(fields));
+    }
+}
+
+/// Reads the trace context from the `OtelData` extension of `span`, if the span is tracked
+/// by `tracing-opentelemetry` and has a valid OpenTelemetry span context.
+#[cfg(feature = "opentelemetry")]
+fn otel_trace_context<'a, R: LookupSpan<'a>>(
+    span: &SpanRef<'a, R>,
+    dispatch: &Dispatch,
+) -> Option<Map<String, Value>> {
+    use opentelemetry::trace::TraceContextExt;
+
+    let context = tracing_opentelemetry::get_otel_context(&mut span.extensions_mut(), dispatch)?;
+    let otel_span = context.span();
+    let span_context = otel_span.span_context();
+    if !span_context.is_valid() {
+        return None;
+    }
+    let mut fields = Map::new();
+    fields.insert(
+        "trace_id".to_string(),
+        json!(span_context.trace_id().to_string()),
+    );
+    fields.insert(
+        "span_id".to_string(),
+        json!(span_context.span_id().to_string()),
+    );
+    fields.insert(
+        "trace_flags".to_string(),
+        json!(format!("{:02x}", span_context.trace_flags().to_u8())),
+    );
+    Some(fields)
+}
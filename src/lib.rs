@@ -1,4 +1,7 @@
 pub mod config;
+pub mod error;
 pub mod layer;
 pub mod s3_helpers;
+pub mod sink;
+pub mod spool;
 pub mod testing;
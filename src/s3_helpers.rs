@@ -1,4 +1,7 @@
+use crate::config::retry_policy::RetryPolicy;
+use crate::error::{Result, TracingS3Error};
 use aws_sdk_s3::Client;
+use aws_sdk_s3::error::SdkError;
 use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_s3::types::ChecksumAlgorithm;
 
@@ -8,43 +11,234 @@ pub struct S3Helpers {}
 
 impl S3Helpers {
     /// Retrieves the size of a file in S3.
-    /// 
+    ///
     /// # Arguments
     /// * `client` - The AWS S3 client
     /// * `bucket` - The S3 bucket name
     /// * `key` - The S3 object key
-    /// 
+    ///
     /// # Returns
     /// * `Ok(i64)` - The file size in bytes, or 0 if the file doesn't exist
-    /// * `Err(anyhow::Error)` - If the head object operation fails
-    pub async fn get_file_size(client: &Client, bucket: &str, key: &str) -> anyhow::Result<i64> {
+    /// * `Err(TracingS3Error)` - If the head object operation fails
+    pub async fn get_file_size(client: &Client, bucket: &str, key: &str) -> Result<i64> {
         let resp = client.head_object().bucket(bucket).key(key).send().await?;
         Ok(resp.content_length.unwrap_or(0))
     }
     /// Appends content to an existing S3 object or creates a new one if it doesn't exist.
     /// Uses S3's write_offset_bytes feature for efficient appending.
-    /// 
+    ///
     /// # Arguments
     /// * `client` - The AWS S3 client
     /// * `bucket` - The S3 bucket name
     /// * `key` - The S3 object key
     /// * `content_to_append` - The content to append to the file
-    /// 
+    ///
     /// # Returns
     /// * `Ok(u64)` - The total file size after appending
-    /// * `Err(anyhow::Error)` - If the append operation fails
+    /// * `Err(TracingS3Error)` - If the append operation fails
     pub async fn append_to_file(
         client: &Client,
         bucket: &str,
         key: &str,
         content_to_append: &str,
-    ) -> anyhow::Result<u64> {
-        let offset = Self::get_file_size(client, bucket, key).await.unwrap_or(0);
+    ) -> Result<u64> {
+        let offset = Self::get_file_size_or_zero(client, bucket, key).await?;
+        Self::write_at_offset(
+            client,
+            bucket,
+            key,
+            offset,
+            content_to_append.as_bytes(),
+            None,
+        )
+        .await
+    }
+
+    /// Appends content to an S3 object, retrying transient failures according to `policy`.
+    ///
+    /// Every attempt re-reads the object size so the write lands at the correct offset.
+    /// If the object grew by exactly the size of the content since the first attempt, the
+    /// new bytes are read back: when they match the content, a previous attempt reached S3
+    /// (e.g. only the response was lost) and the content is not written a second time.
+    /// Otherwise another writer appended to the object and the content is appended after it.
+    ///
+    /// # Arguments
+    /// * `client` - The AWS S3 client
+    /// * `bucket` - The S3 bucket name
+    /// * `key` - The S3 object key
+    /// * `content_to_append` - The content to append to the file
+    /// * `content_encoding` - The `Content-Encoding` set if the append creates the object
+    /// * `policy` - The retry policy to apply
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - The total file size after appending
+    /// * `Err(TracingS3Error)` - The last error, once it is not retryable or attempts run out
+    pub async fn append_with_retry(
+        client: &Client,
+        bucket: &str,
+        key: &str,
+        content_to_append: &[u8],
+        content_encoding: Option<&str>,
+        policy: &RetryPolicy,
+    ) -> Result<u64> {
+        let mut first_offset = None;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let result = async {
+                let offset = Self::get_file_size_or_zero(client, bucket, key).await?;
+                match first_offset {
+                    Some(first)
+                        if offset == first + content_to_append.len() as i64
+                            && Self::get_range(client, bucket, key, first, offset).await?
+                                == content_to_append =>
+                    {
+                        return Ok(offset as u64);
+                    }
+                    Some(_) => {}
+                    None => first_offset = Some(offset),
+                }
+                Self::write_at_offset(
+                    client,
+                    bucket,
+                    key,
+                    offset,
+                    content_to_append,
+                    content_encoding,
+                )
+                .await
+            }
+            .await;
+            match result {
+                Err(err) if attempt < policy.max_attempts() && err.is_retryable() => {
+                    tokio::time::sleep(policy.delay(attempt)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Runs an S3 operation, retrying transient failures according to `policy`.
+    ///
+    /// # Arguments
+    /// * `policy` - The retry policy to apply
+    /// * `operation` - Creates the future for one attempt
+    ///
+    /// # Returns
+    /// * `Ok(T)` - The result of the first successful attempt
+    /// * `Err(TracingS3Error)` - The last error, once it is not retryable or attempts run out
+    pub async fn retry<T, F, Fut>(policy: &RetryPolicy, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match operation().await {
+                Err(err) if attempt < policy.max_attempts() && err.is_retryable() => {
+                    tokio::time::sleep(policy.delay(attempt)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Downloads an S3 object, treating a missing object as empty.
+    ///
+    /// # Arguments
+    /// * `client` - The AWS S3 client
+    /// * `bucket` - The S3 bucket name
+    /// * `key` - The S3 object key
+    ///
+    /// # Returns
+    /// * `Ok(Vec<u8>)` - The object content, or an empty vector if the object doesn't exist
+    /// * `Err(TracingS3Error)` - If the get object operation fails for another reason
+    pub async fn get_file_or_empty(client: &Client, bucket: &str, key: &str) -> Result<Vec<u8>> {
+        match client.get_object().bucket(bucket).key(key).send().await {
+            Ok(resp) => Ok(resp
+                .body
+                .collect()
+                .await
+                .map_err(TracingS3Error::s3_transport)?
+                .to_vec()),
+            Err(SdkError::ServiceError(service)) if service.err().is_no_such_key() => Ok(vec![]),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Downloads a byte range of an S3 object.
+    ///
+    /// # Arguments
+    /// * `client` - The AWS S3 client
+    /// * `bucket` - The S3 bucket name
+    /// * `key` - The S3 object key
+    /// * `start` - The offset of the first byte
+    /// * `end` - The offset after the last byte
+    ///
+    /// # Returns
+    /// * `Ok(Vec<u8>)` - The content of the range, empty if `end` is not after `start`
+    /// * `Err(TracingS3Error)` - If the get object operation fails
+    pub async fn get_range(
+        client: &Client,
+        bucket: &str,
+        key: &str,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<u8>> {
+        if start >= end {
+            return Ok(vec![]);
+        }
+        let resp = client
+            .get_object()
+            .bucket(bucket)
+            .key(key)
+            .range(format!("bytes={start}-{}", end - 1))
+            .send()
+            .await?;
+        Ok(resp
+            .body
+            .collect()
+            .await
+            .map_err(TracingS3Error::s3_transport)?
+            .to_vec())
+    }
+
+    /// Returns the size of a file in S3, treating a missing object as empty.
+    ///
+    /// # Arguments
+    /// * `client` - The AWS S3 client
+    /// * `bucket` - The S3 bucket name
+    /// * `key` - The S3 object key
+    ///
+    /// # Returns
+    /// * `Ok(i64)` - The file size in bytes, or 0 if the file doesn't exist
+    /// * `Err(TracingS3Error)` - If the head object operation fails for another reason
+    pub async fn get_file_size_or_zero(client: &Client, bucket: &str, key: &str) -> Result<i64> {
+        match client.head_object().bucket(bucket).key(key).send().await {
+            Ok(resp) => Ok(resp.content_length.unwrap_or(0)),
+            Err(SdkError::ServiceError(service)) if service.err().is_not_found() => Ok(0),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes content at the given offset of an S3 object.
+    async fn write_at_offset(
+        client: &Client,
+        bucket: &str,
+        key: &str,
+        offset: i64,
+        content_to_append: &[u8],
+        content_encoding: Option<&str>,
+    ) -> Result<u64> {
         let total_len = offset as u64 + content_to_append.len() as u64;
-        let content_to_append = content_to_append.as_bytes().to_vec();
+        let content_to_append = content_to_append.to_vec();
+        // Object metadata can only be set when the object is created.
+        let content_encoding = content_encoding.filter(|_| offset == 0);
         client
             .put_object()
             .set_write_offset_bytes(Some(offset))
+            .set_content_encoding(content_encoding.map(str::to_string))
             .checksum_algorithm(ChecksumAlgorithm::Crc64Nvme)
             .bucket(bucket)
             .key(key)
@@ -58,12 +252,15 @@ impl S3Helpers {
 #[cfg(test)]
 mod tests {
 
+    use crate::config::retry_policy::RetryPolicy;
     use crate::config::tracing_s3_config::TracingS3Config;
     use crate::config::types::{
         Bucket, BufferSizeLimitKb, CronIntervalInMs, Endpoint, ObjectSizeLimitMb, Postfix, Prefix,
     };
     use crate::s3_helpers::S3Helpers;
+    use crate::testing::fake_s3::{BUCKET, FakeS3};
     use chrono::Utc;
+    use std::time::Duration;
 
     #[tokio::test]
     pub async fn append_to_file_test() {
@@ -91,4 +288,119 @@ mod tests {
             .await;
         }
     }
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy::new(3, 1, 5, true).unwrap()
+    }
+
+    #[tokio::test]
+    pub async fn append_with_retry_recovers_from_transient_errors() {
+        let fake = FakeS3::start().await;
+        let client = fake.client();
+        fake.fail_next(503, "SlowDown");
+        fake.fail_next(500, "InternalError");
+        let size =
+            S3Helpers::append_with_retry(&client, BUCKET, "key", b"hello\n", None, &fast_policy())
+                .await
+                .unwrap();
+        assert_eq!(size, 6);
+        let size =
+            S3Helpers::append_with_retry(&client, BUCKET, "key", b"world\n", None, &fast_policy())
+                .await
+                .unwrap();
+        assert_eq!(size, 12);
+        assert_eq!(fake.object("key").unwrap(), b"hello\nworld\n");
+    }
+
+    #[tokio::test]
+    pub async fn append_with_retry_does_not_retry_permanent_errors() {
+        let fake = FakeS3::start().await;
+        fake.fail_next(403, "AccessDenied");
+        let result = S3Helpers::append_with_retry(
+            &fake.client(),
+            BUCKET,
+            "key",
+            b"hello",
+            None,
+            &fast_policy(),
+        )
+        .await;
+        let err = result.unwrap_err();
+        assert!(!err.is_retryable());
+        assert_eq!(err.status_code(), Some(403));
+        assert_eq!(fake.requests().len(), 1);
+        assert!(fake.object("key").is_none());
+    }
+
+    #[tokio::test]
+    pub async fn append_with_retry_gives_up_after_max_attempts() {
+        let fake = FakeS3::start().await;
+        for _ in 0..3 {
+            fake.fail_next(503, "SlowDown");
+        }
+        let result = S3Helpers::append_with_retry(
+            &fake.client(),
+            BUCKET,
+            "key",
+            b"hello",
+            None,
+            &fast_policy(),
+        )
+        .await;
+        assert!(result.unwrap_err().is_retryable());
+        assert_eq!(fake.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    pub async fn append_with_retry_retries_failed_put_at_same_offset() {
+        let fake = FakeS3::start().await;
+        let client = fake.client();
+        S3Helpers::append_to_file(&client, BUCKET, "key", "first\n")
+            .await
+            .unwrap();
+        // Let the HEAD through, then fail the PUT itself.
+        fake.pass_next();
+        fake.fail_next(503, "SlowDown");
+        S3Helpers::append_with_retry(&client, BUCKET, "key", b"second\n", None, &fast_policy())
+            .await
+            .unwrap();
+        assert_eq!(fake.object("key").unwrap(), b"first\nsecond\n");
+    }
+
+    #[tokio::test]
+    pub async fn append_with_retry_does_not_repeat_a_write_that_landed() {
+        let fake = FakeS3::start().await;
+        let client = fake.client();
+        fake.pass_next();
+        fake.lose_next_response(503, "SlowDown");
+        S3Helpers::append_with_retry(&client, BUCKET, "key", b"first\n", None, &fast_policy())
+            .await
+            .unwrap();
+        assert_eq!(fake.object("key").unwrap(), b"first\n");
+    }
+
+    #[tokio::test]
+    pub async fn append_with_retry_appends_after_another_writer() {
+        let fake = FakeS3::start().await;
+        let client = fake.client();
+        S3Helpers::append_to_file(&client, BUCKET, "key", "first\n")
+            .await
+            .unwrap();
+        fake.pass_next();
+        fake.fail_next(503, "SlowDown");
+        let policy = RetryPolicy::new(2, 200, 200, false).unwrap();
+        let append = tokio::spawn({
+            let client = client.clone();
+            async move {
+                S3Helpers::append_with_retry(&client, BUCKET, "key", b"ours\n", None, &policy).await
+            }
+        });
+        // Another writer appends as many bytes while the first attempt backs off.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        S3Helpers::append_to_file(&client, BUCKET, "key", "them\n")
+            .await
+            .unwrap();
+        append.await.unwrap().unwrap();
+        assert_eq!(fake.object("key").unwrap(), b"first\nthem\nours\n");
+    }
 }
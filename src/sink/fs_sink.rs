@@ -0,0 +1,130 @@
+use crate::error::{Result, TracingS3Error};
+use crate::sink::LogSink;
+use std::path::{Component, Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// [`LogSink`] writing objects as files below a local directory.
+/// Object keys map to relative paths, so `2024-01-01/0/app.log` becomes a nested file.
+/// Keys that are absolute or contain `..` are rejected, so no file outside of the root is
+/// written. Handy for local development.
+#[derive(Debug, Clone)]
+pub struct FsSink {
+    root: PathBuf,
+}
+
+impl FsSink {
+    /// Creates a new FsSink instance.
+    ///
+    /// # Arguments
+    /// * `root` - The directory objects are written to, created on demand
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Returns the path of the object, rejecting keys that would leave the root directory.
+    fn resolve(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        if relative.components().any(|component| {
+            matches!(
+                component,
+                Component::ParentDir | Component::RootDir | Component::Prefix(_)
+            )
+        }) {
+            return Err(TracingS3Error::invalid_config(
+                "key_template",
+                format!("Object key `{key}` must stay inside the sink directory"),
+            ));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// Returns the path of the object, creating its parent directories.
+    async fn path(&self, key: &str) -> Result<PathBuf> {
+        let path = self.resolve(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        Ok(path)
+    }
+}
+
+impl LogSink for FsSink {
+    async fn append(&self, key: &str, content: &[u8]) -> Result<u64> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(key).await?)
+            .await?;
+        file.write_all(content).await?;
+        file.flush().await?;
+        Ok(file.metadata().await?.len())
+    }
+
+    async fn put(&self, key: &str, content: &[u8]) -> Result<()> {
+        tokio::fs::write(self.path(key).await?, content).await?;
+        Ok(())
+    }
+
+    async fn finalize(&self, _key: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn size(&self, key: &str) -> Result<u64> {
+        match tokio::fs::metadata(self.resolve(key)?).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn is_retryable(&self, err: &TracingS3Error) -> bool {
+        match err {
+            TracingS3Error::Io(err) => !matches!(
+                err.kind(),
+                std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::InvalidInput
+            ),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sink::LogSink;
+    use crate::sink::fs_sink::FsSink;
+
+    #[tokio::test]
+    async fn appends_to_nested_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FsSink::new(dir.path());
+        assert_eq!(sink.size("a/b.log").await.unwrap(), 0);
+        assert_eq!(sink.append("a/b.log", b"one\n").await.unwrap(), 4);
+        assert_eq!(sink.append("a/b.log", b"two\n").await.unwrap(), 8);
+        assert_eq!(
+            std::fs::read(dir.path().join("a/b.log")).unwrap(),
+            b"one\ntwo\n"
+        );
+        sink.put("a/b.log", b"new").await.unwrap();
+        assert_eq!(sink.size("a/b.log").await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn rejects_keys_outside_of_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let sink = FsSink::new(&root);
+        let escape = dir.path().join("escaped.log");
+        for key in [
+            "../escaped.log",
+            "a/../../escaped.log",
+            escape.to_str().unwrap(),
+        ] {
+            let err = sink.append(key, b"line\n").await.unwrap_err();
+            assert_eq!(err.field(), Some("key_template"), "{err}");
+            assert!(!sink.is_retryable(&err));
+            assert!(sink.put(key, b"line\n").await.is_err());
+            assert!(sink.size(key).await.is_err());
+        }
+        assert!(!escape.exists());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::error::{Result, TracingS3Error};
+use crate::sink::LogSink;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Error returned by [`MemorySink`] for failures injected with [`MemorySink::fail_next`].
+#[derive(Debug)]
+pub struct InjectedFailure {
+    pub retryable: bool,
+}
+
+impl std::fmt::Display for InjectedFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Injected failure (retryable: {})", self.retryable)
+    }
+}
+
+impl std::error::Error for InjectedFailure {}
+
+#[derive(Debug, Default)]
+struct MemorySinkState {
+    objects: HashMap<String, Vec<u8>>,
+    finalized: Vec<String>,
+    failures: VecDeque<bool>,
+    appends: usize,
+}
+
+/// [`LogSink`] keeping objects in memory, for tests.
+///
+/// Clones share the same storage, so a test can hand one clone to the layer and inspect
+/// the objects through another. Failures can be injected with [`MemorySink::fail_next`].
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    state: Arc<Mutex<MemorySinkState>>,
+}
+
+impl MemorySink {
+    /// Creates a new, empty MemorySink instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the next `append` or `put` call fail.
+    ///
+    /// # Arguments
+    /// * `retryable` - Whether the failure should be classified as transient
+    pub fn fail_next(&self, retryable: bool) {
+        self.state.lock().unwrap().failures.push_back(retryable);
+    }
+
+    /// Returns a copy of the object stored under `key`, if any.
+    pub fn object(&self, key: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().objects.get(key).cloned()
+    }
+
+    /// Returns all stored objects sorted by key.
+    pub fn objects(&self) -> Vec<(String, Vec<u8>)> {
+        let mut objects: Vec<_> = self
+            .state
+            .lock()
+            .unwrap()
+            .objects
+            .iter()
+            .map(|(key, object)| (key.clone(), object.clone()))
+            .collect();
+        objects.sort();
+        objects
+    }
+
+    /// Returns the keys passed to `finalize`, in call order.
+    pub fn finalized(&self) -> Vec<String> {
+        self.state.lock().unwrap().finalized.clone()
+    }
+
+    /// Returns the number of successful appends.
+    pub fn appends(&self) -> usize {
+        self.state.lock().unwrap().appends
+    }
+
+    fn take_failure(state: &mut MemorySinkState) -> Result<()> {
+        match state.failures.pop_front() {
+            Some(retryable) => Err(TracingS3Error::Sink(Box::new(InjectedFailure {
+                retryable,
+            }))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl LogSink for MemorySink {
+    async fn append(&self, key: &str, content: &[u8]) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        Self::take_failure(&mut state)?;
+        state.appends += 1;
+        let object = state.objects.entry(key.to_string()).or_default();
+        object.extend_from_slice(content);
+        Ok(object.len() as u64)
+    }
+
+    async fn put(&self, key: &str, content: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        Self::take_failure(&mut state)?;
+        state.objects.insert(key.to_string(), content.to_vec());
+        Ok(())
+    }
+
+    async fn finalize(&self, key: &str) -> Result<()> {
+        self.state.lock().unwrap().finalized.push(key.to_string());
+        Ok(())
+    }
+
+    async fn size(&self, key: &str) -> Result<u64> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .objects
+            .get(key)
+            .map_or(0, |object| object.len() as u64))
+    }
+
+    fn is_retryable(&self, err: &TracingS3Error) -> bool {
+        match err {
+            TracingS3Error::Sink(err) => err
+                .downcast_ref::<InjectedFailure>()
+                .is_some_and(|failure| failure.retryable),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+pub mod fs_sink;
+pub mod memory_sink;
+pub mod s3_sink;
+
+use crate::error::{Result, TracingS3Error};
+use std::future::Future;
+
+/// Destination for uploaded log data.
+///
+/// [`HttpLogLayer`](crate::layer::http_log_layer::HttpLogLayer) is generic over this trait,
+/// so the same buffering, rotation and spooling logic can write to S3 Express One Zone
+/// ([`s3_sink::S3Sink`]), the local filesystem ([`fs_sink::FsSink`]), memory
+/// ([`memory_sink::MemorySink`]) or any other store.
+///
+/// Objects are addressed by key. The layer may append to several objects in turn: one per
+/// level with a `{level}` key template (see
+/// [`KeyTemplate`](crate::config::key_template::KeyTemplate)), and the earlier objects of
+/// spooled or requeued batches. It calls [`LogSink::finalize`] once it moves on from an object,
+/// and again after writing more to an object it had already finalized.
+pub trait LogSink: Send + Sync + 'static {
+    /// Appends content to an object, creating it if it doesn't exist.
+    ///
+    /// # Arguments
+    /// * `key` - The object key
+    /// * `content` - The content to append
+    ///
+    /// # Returns
+    /// * `Ok(u64)` - The total object size after appending
+    /// * `Err(TracingS3Error)` - If the append failed
+    fn append(&self, key: &str, content: &[u8]) -> impl Future<Output = Result<u64>> + Send;
+
+    /// Writes an object in full, replacing any previous content.
+    ///
+    /// # Arguments
+    /// * `key` - The object key
+    /// * `content` - The object content
+    fn put(&self, key: &str, content: &[u8]) -> impl Future<Output = Result<()>> + Send;
+
+    /// Signals that no more content will be appended to an object.
+    ///
+    /// # Arguments
+    /// * `key` - The object key
+    fn finalize(&self, key: &str) -> impl Future<Output = Result<()>> + Send;
+
+    /// Returns the current size of an object in bytes, or 0 if it doesn't exist.
+    ///
+    /// # Arguments
+    /// * `key` - The object key
+    fn size(&self, key: &str) -> impl Future<Output = Result<u64>> + Send;
+
+    /// Classifies an error returned by this sink as transient (worth trying again later)
+    /// or permanent. Payloads that failed with a transient error are kept by the layer.
+    ///
+    /// # Arguments
+    /// * `err` - An error returned by one of the sink's methods
+    fn is_retryable(&self, err: &TracingS3Error) -> bool;
+}
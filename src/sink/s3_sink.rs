@@ -0,0 +1,573 @@
+use crate::config::retry_policy::RetryPolicy;
+use crate::error::{Result, TracingS3Error};
+use crate::s3_helpers::S3Helpers;
+use crate::sink::LogSink;
+use aws_sdk_s3::Client;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+/// The smallest part size S3 accepts for every part of a multipart upload except the last.
+pub const MIN_PART_SIZE: usize = 5 * 1_024 * 1_024;
+
+/// Characters kept as they are in the `x-amz-copy-source` header: the unreserved ones and
+/// the `/` between key segments.
+const COPY_SOURCE_KEY: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// How [`S3Sink`] writes to a bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum UploadStrategy {
+    /// [`UploadStrategy::Append`] for S3 Express One Zone directory buckets (names ending in
+    /// `--x-s3`), [`UploadStrategy::Multipart`] for every other bucket.
+    Auto,
+    /// Appends with `write_offset_bytes`. Only supported by directory buckets.
+    Append,
+    /// Uploads appended data as the parts of a multipart upload, completed when the object
+    /// is finalized (rotated or on shutdown). Works with any S3-compatible store, but the
+    /// object only becomes visible once it is finalized.
+    Multipart,
+    /// Keeps the object's content in memory and re-uploads it in full after every append.
+    /// Works with any S3-compatible store and keeps the object readable at all times, at the
+    /// cost of memory and bandwidth growing with the object size.
+    RollingPut,
+}
+
+impl UploadStrategy {
+    /// Resolves [`UploadStrategy::Auto`] based on the bucket type.
+    ///
+    /// # Arguments
+    /// * `bucket` - The S3 bucket name
+    pub fn resolve(self, bucket: &str) -> Self {
+        match self {
+            UploadStrategy::Auto if bucket.ends_with("--x-s3") => UploadStrategy::Append,
+            UploadStrategy::Auto => UploadStrategy::Multipart,
+            strategy => strategy,
+        }
+    }
+}
+
+/// An open multipart upload.
+///
+/// Parts of at least the part size are sealed. The data after them is the tail, which is
+/// uploaded as the next part on every append and replaced as the tail grows, so every
+/// appended byte is stored in S3 while at most one part size is re-sent per append.
+#[derive(Debug, Default)]
+struct MultipartState {
+    upload_id: String,
+    parts: Vec<CompletedPart>,
+    tail: Vec<u8>,
+    sealed_bytes: u64,
+}
+
+/// [`LogSink`] writing to S3.
+///
+/// Uses S3 Express One Zone appends (`write_offset_bytes`), multipart uploads or rolling
+/// whole-object PUTs depending on its [`UploadStrategy`]. Every request is retried according
+/// to a [`RetryPolicy`].
+#[derive(Debug)]
+pub struct S3Sink {
+    client: Client,
+    bucket: String,
+    retry_policy: RetryPolicy,
+    strategy: UploadStrategy,
+    part_size: usize,
+    content_encoding: Option<String>,
+    multipart: Mutex<HashMap<String, MultipartState>>,
+    rolling: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl S3Sink {
+    /// Creates a new S3Sink instance using [`UploadStrategy::Auto`].
+    ///
+    /// # Arguments
+    /// * `client` - The AWS S3 client
+    /// * `bucket` - The S3 bucket name
+    /// * `retry_policy` - The retry policy applied to every request
+    pub fn new(client: Client, bucket: &str, retry_policy: RetryPolicy) -> Self {
+        Self {
+            client,
+            bucket: bucket.to_string(),
+            retry_policy,
+            strategy: UploadStrategy::Auto.resolve(bucket),
+            part_size: MIN_PART_SIZE,
+            content_encoding: None,
+            multipart: Mutex::new(HashMap::new()),
+            rolling: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the upload strategy.
+    ///
+    /// # Arguments
+    /// * `strategy` - The upload strategy, [`UploadStrategy::Auto`] is resolved from the bucket name
+    pub fn with_strategy(mut self, strategy: UploadStrategy) -> Self {
+        self.strategy = strategy.resolve(&self.bucket);
+        self
+    }
+
+    /// Sets the part size used by [`UploadStrategy::Multipart`].
+    /// S3 rejects parts smaller than [`MIN_PART_SIZE`] (except the last one).
+    ///
+    /// # Arguments
+    /// * `part_size` - The part size in bytes
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.max(1);
+        self
+    }
+
+    /// Sets the `Content-Encoding` of the objects this sink creates, e.g. `gzip`.
+    ///
+    /// # Arguments
+    /// * `content_encoding` - The content encoding, `None` to leave it unset
+    pub fn with_content_encoding(mut self, content_encoding: Option<&str>) -> Self {
+        self.content_encoding = content_encoding.map(str::to_string);
+        self
+    }
+
+    /// Returns the resolved upload strategy.
+    pub fn strategy(&self) -> UploadStrategy {
+        self.strategy
+    }
+
+    /// Starts a multipart upload. Content already stored under the key is carried over,
+    /// so an object that gets appended to again is extended rather than replaced: an object
+    /// of at least the part size is copied into the first part on the S3 side, a smaller one
+    /// is downloaded into the tail.
+    async fn create_multipart_upload(&self, key: &str) -> Result<MultipartState> {
+        let existing_size = S3Helpers::retry(&self.retry_policy, || {
+            S3Helpers::get_file_size_or_zero(&self.client, &self.bucket, key)
+        })
+        .await?;
+        let tail = if existing_size > 0 && (existing_size as usize) < self.part_size {
+            S3Helpers::retry(&self.retry_policy, || {
+                S3Helpers::get_file_or_empty(&self.client, &self.bucket, key)
+            })
+            .await?
+        } else {
+            vec![]
+        };
+        let upload_id = S3Helpers::retry(&self.retry_policy, || async {
+            let resp = self
+                .client
+                .create_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .set_content_encoding(self.content_encoding.clone())
+                .send()
+                .await?;
+            Ok(resp.upload_id.unwrap_or_default())
+        })
+        .await?;
+        let mut state = MultipartState {
+            upload_id,
+            tail,
+            ..MultipartState::default()
+        };
+        if existing_size as usize >= self.part_size {
+            let copied = S3Helpers::retry(&self.retry_policy, || async {
+                let resp = self
+                    .client
+                    .upload_part_copy()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .upload_id(&state.upload_id)
+                    .part_number(1)
+                    .copy_source(format!(
+                        "{}/{}",
+                        self.bucket,
+                        utf8_percent_encode(key, COPY_SOURCE_KEY)
+                    ))
+                    .send()
+                    .await?;
+                Ok(CompletedPart::builder()
+                    .part_number(1)
+                    .set_e_tag(resp.copy_part_result.and_then(|result| result.e_tag))
+                    .build())
+            })
+            .await;
+            match copied {
+                Ok(part) => {
+                    state.parts.push(part);
+                    state.sealed_bytes = existing_size as u64;
+                }
+                Err(err) => {
+                    self.abort_multipart_upload(key, &state.upload_id).await;
+                    return Err(err);
+                }
+            }
+        }
+        Ok(state)
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        body: &[u8],
+    ) -> Result<CompletedPart> {
+        S3Helpers::retry(&self.retry_policy, || async {
+            let resp = self
+                .client
+                .upload_part()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .body(ByteStream::from(body.to_vec()))
+                .send()
+                .await?;
+            Ok(CompletedPart::builder()
+                .part_number(part_number)
+                .set_e_tag(resp.e_tag)
+                .build())
+        })
+        .await
+    }
+
+    /// Aborts a multipart upload, so S3 discards its parts. Failures are ignored; an upload
+    /// that cannot be aborted is left to the bucket's lifecycle rules.
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) {
+        let _ = S3Helpers::retry(&self.retry_policy, || async {
+            self.client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await?;
+            Ok(())
+        })
+        .await;
+    }
+
+    /// Adds content to the multipart upload of `key`, starting the upload if needed.
+    ///
+    /// The tail and the content are uploaded together as the next part, which is sealed
+    /// once it reaches the part size. The state is only updated once the part upload
+    /// succeeded, so on a transient error the content was not taken and the caller still
+    /// owns it. On a permanent error the upload is aborted.
+    async fn append_multipart(&self, key: &str, content: &[u8]) -> Result<u64> {
+        let mut uploads = self.multipart.lock().await;
+        if !uploads.contains_key(key) {
+            let state = self.create_multipart_upload(key).await?;
+            uploads.insert(key.to_string(), state);
+        }
+        let state = uploads
+            .get_mut(key)
+            .expect("Multipart state was just inserted");
+        let mut body = Vec::with_capacity(state.tail.len() + content.len());
+        body.extend_from_slice(&state.tail);
+        body.extend_from_slice(content);
+        let part_number = state.parts.len() as i32 + 1;
+        match self
+            .upload_part(key, &state.upload_id, part_number, &body)
+            .await
+        {
+            Ok(part) if body.len() >= self.part_size => {
+                state.parts.push(part);
+                state.sealed_bytes += body.len() as u64;
+                state.tail.clear();
+            }
+            Ok(_) => state.tail = body,
+            Err(err) => {
+                if !self.is_retryable(&err)
+                    && let Some(state) = uploads.remove(key)
+                {
+                    self.abort_multipart_upload(key, &state.upload_id).await;
+                }
+                return Err(err);
+            }
+        }
+        Ok(state.sealed_bytes + state.tail.len() as u64)
+    }
+
+    /// Uploads the tail of `key` as its last part and completes its multipart upload.
+    ///
+    /// The tail is uploaded again even though an append already sent it, as the part may
+    /// have been replaced by an append whose response was lost. On a transient error the
+    /// upload stays open, so finalizing can be retried; on a permanent error it is aborted.
+    async fn finalize_multipart(&self, key: &str) -> Result<()> {
+        let mut uploads = self.multipart.lock().await;
+        let Some(state) = uploads.get(key) else {
+            return Ok(());
+        };
+        let result = async {
+            let mut parts = state.parts.clone();
+            if !state.tail.is_empty() || parts.is_empty() {
+                let part_number = parts.len() as i32 + 1;
+                parts.push(
+                    self.upload_part(key, &state.upload_id, part_number, &state.tail)
+                        .await?,
+                );
+            }
+            let upload = CompletedMultipartUpload::builder()
+                .set_parts(Some(parts))
+                .build();
+            S3Helpers::retry(&self.retry_policy, || async {
+                self.client
+                    .complete_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .upload_id(&state.upload_id)
+                    .multipart_upload(upload.clone())
+                    .send()
+                    .await?;
+                Ok(())
+            })
+            .await
+        }
+        .await;
+        match result {
+            Err(err) if self.is_retryable(&err) => Err(err),
+            result => {
+                if let Some(state) = uploads.remove(key)
+                    && result.is_err()
+                {
+                    self.abort_multipart_upload(key, &state.upload_id).await;
+                }
+                result
+            }
+        }
+    }
+
+    /// Appends content to the in-memory copy of `key` and re-uploads the whole object.
+    async fn append_rolling(&self, key: &str, content: &[u8]) -> Result<u64> {
+        let mut objects = self.rolling.lock().await;
+        if !objects.contains_key(key) {
+            let existing = S3Helpers::retry(&self.retry_policy, || {
+                S3Helpers::get_file_or_empty(&self.client, &self.bucket, key)
+            })
+            .await?;
+            objects.insert(key.to_string(), existing);
+        }
+        let object = objects
+            .get_mut(key)
+            .expect("Rolling object was just inserted");
+        let mut body = Vec::with_capacity(object.len() + content.len());
+        body.extend_from_slice(object);
+        body.extend_from_slice(content);
+        S3Helpers::retry(&self.retry_policy, || self.put(key, &body)).await?;
+        *object = body;
+        Ok(object.len() as u64)
+    }
+}
+
+impl LogSink for S3Sink {
+    async fn append(&self, key: &str, content: &[u8]) -> Result<u64> {
+        match self.strategy {
+            UploadStrategy::Auto | UploadStrategy::Append => {
+                S3Helpers::append_with_retry(
+                    &self.client,
+                    &self.bucket,
+                    key,
+                    content,
+                    self.content_encoding.as_deref(),
+                    &self.retry_policy,
+                )
+                .await
+            }
+            UploadStrategy::Multipart => self.append_multipart(key, content).await,
+            UploadStrategy::RollingPut => self.append_rolling(key, content).await,
+        }
+    }
+
+    async fn put(&self, key: &str, content: &[u8]) -> Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_content_encoding(self.content_encoding.clone())
+            .body(ByteStream::from(content.to_vec()))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn finalize(&self, key: &str) -> Result<()> {
+        match self.strategy {
+            UploadStrategy::Multipart => self.finalize_multipart(key).await,
+            UploadStrategy::RollingPut => {
+                self.rolling.lock().await.remove(key);
+                Ok(())
+            }
+            UploadStrategy::Auto | UploadStrategy::Append => Ok(()),
+        }
+    }
+
+    async fn size(&self, key: &str) -> Result<u64> {
+        if let Some(state) = self.multipart.lock().await.get(key) {
+            return Ok(state.sealed_bytes + state.tail.len() as u64);
+        }
+        if let Some(object) = self.rolling.lock().await.get(key) {
+            return Ok(object.len() as u64);
+        }
+        Ok(S3Helpers::get_file_size_or_zero(&self.client, &self.bucket, key).await? as u64)
+    }
+
+    fn is_retryable(&self, err: &TracingS3Error) -> bool {
+        err.is_retryable()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sink::LogSink;
+    use crate::sink::s3_sink::UploadStrategy;
+    use crate::testing::fake_s3::{BUCKET, FakeS3};
+
+    #[test]
+    fn auto_strategy_depends_on_bucket_type() {
+        assert_eq!(
+            UploadStrategy::Auto.resolve("logs--usw2-az1--x-s3"),
+            UploadStrategy::Append
+        );
+        assert_eq!(
+            UploadStrategy::Auto.resolve("logs"),
+            UploadStrategy::Multipart
+        );
+        assert_eq!(
+            UploadStrategy::RollingPut.resolve("logs--usw2-az1--x-s3"),
+            UploadStrategy::RollingPut
+        );
+    }
+
+    #[tokio::test]
+    async fn multipart_uploads_full_parts_and_completes_on_finalize() {
+        let s3 = FakeS3::start().await;
+        let sink = s3
+            .sink()
+            .with_strategy(UploadStrategy::Multipart)
+            .with_part_size(4);
+        assert_eq!(sink.append("key", b"ab").await.unwrap(), 2);
+        assert_eq!(sink.append("key", b"cdefg").await.unwrap(), 7);
+        assert_eq!(sink.append("key", b"hij").await.unwrap(), 10);
+        assert_eq!(sink.size("key").await.unwrap(), 10);
+        assert_eq!(s3.object("key"), None);
+        assert_eq!(s3.open_uploads(), 1);
+
+        sink.finalize("key").await.unwrap();
+        assert_eq!(s3.object("key").as_deref(), Some(&b"abcdefghij"[..]));
+        assert_eq!(s3.open_uploads(), 0);
+        // One part per append, the tail once more on finalize.
+        let parts: Vec<_> = s3
+            .requests()
+            .into_iter()
+            .filter(|r| r.query.contains("partNumber"))
+            .map(|r| r.body)
+            .collect();
+        assert_eq!(parts, vec![&b"ab"[..], b"abcdefg", b"hij", b"hij"]);
+    }
+
+    #[tokio::test]
+    async fn multipart_aborts_the_upload_on_a_permanent_error() {
+        let s3 = FakeS3::start().await;
+        let sink = s3
+            .sink()
+            .with_strategy(UploadStrategy::Multipart)
+            .with_part_size(4);
+        sink.append("key", b"ab").await.unwrap();
+        s3.fail_next(403, "AccessDenied");
+        let err = sink.append("key", b"cd").await.unwrap_err();
+        assert!(!sink.is_retryable(&err));
+        assert_eq!(s3.open_uploads(), 0);
+        assert!(s3.requests().iter().any(|r| r.method == "DELETE"));
+        sink.finalize("key").await.unwrap();
+        assert_eq!(s3.object("key"), None);
+    }
+
+    #[tokio::test]
+    async fn multipart_extends_an_existing_object() {
+        let s3 = FakeS3::start().await;
+        s3.sink().put("small", b"ab").await.unwrap();
+        s3.sink().put("large", b"abcdef").await.unwrap();
+        let sink = s3
+            .sink()
+            .with_strategy(UploadStrategy::Multipart)
+            .with_part_size(4);
+        for key in ["small", "large"] {
+            sink.append(key, b"gh").await.unwrap();
+            sink.finalize(key).await.unwrap();
+        }
+        assert_eq!(s3.object("small").as_deref(), Some(&b"abgh"[..]));
+        assert_eq!(s3.object("large").as_deref(), Some(&b"abcdefgh"[..]));
+        // The large object is copied on the S3 side instead of being downloaded.
+        let downloads: Vec<_> = s3
+            .requests()
+            .into_iter()
+            .filter(|r| r.method == "GET")
+            .map(|r| r.key)
+            .collect();
+        assert_eq!(downloads, vec!["small".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn multipart_copies_objects_with_reserved_characters_in_the_key() {
+        let s3 = FakeS3::start().await;
+        let key = "dt=2024-01-02/hour=11/my service+café 100%.log";
+        s3.sink().put(key, b"abcdef").await.unwrap();
+        let sink = s3
+            .sink()
+            .with_strategy(UploadStrategy::Multipart)
+            .with_part_size(4);
+        sink.append(key, b"gh").await.unwrap();
+        sink.finalize(key).await.unwrap();
+        assert_eq!(s3.object(key).as_deref(), Some(&b"abcdefgh"[..]));
+        let copy_source = s3
+            .requests()
+            .into_iter()
+            .find_map(|r| r.headers.get("x-amz-copy-source").cloned())
+            .unwrap();
+        assert_eq!(
+            copy_source,
+            format!("{BUCKET}/dt%3D2024-01-02/hour%3D11/my%20service%2Bcaf%C3%A9%20100%25.log")
+        );
+    }
+
+    #[tokio::test]
+    async fn multipart_keeps_content_when_a_part_fails() {
+        let s3 = FakeS3::start().await;
+        let sink = s3
+            .sink()
+            .with_strategy(UploadStrategy::Multipart)
+            .with_part_size(4);
+        sink.append("key", b"ab").await.unwrap();
+        for _ in 0..3 {
+            s3.fail_next(503, "SlowDown");
+        }
+        let err = sink.append("key", b"cdef").await.unwrap_err();
+        assert!(sink.is_retryable(&err));
+        assert_eq!(sink.size("key").await.unwrap(), 2);
+
+        sink.append("key", b"cdef").await.unwrap();
+        sink.finalize("key").await.unwrap();
+        assert_eq!(s3.object("key").as_deref(), Some(&b"abcdef"[..]));
+    }
+
+    #[tokio::test]
+    async fn rolling_put_rewrites_the_whole_object() {
+        let s3 = FakeS3::start().await;
+        s3.sink().put("key", b"old\n").await.unwrap();
+        let sink = s3.sink().with_strategy(UploadStrategy::RollingPut);
+        assert_eq!(sink.append("key", b"first\n").await.unwrap(), 10);
+        assert_eq!(s3.object("key").as_deref(), Some(&b"old\nfirst\n"[..]));
+        assert_eq!(sink.append("key", b"second\n").await.unwrap(), 17);
+        assert_eq!(
+            s3.object("key").as_deref(),
+            Some(&b"old\nfirst\nsecond\n"[..])
+        );
+        sink.finalize("key").await.unwrap();
+    }
+}
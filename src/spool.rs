@@ -0,0 +1,374 @@
+use crate::error::{Result, TracingS3Error};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const SEGMENT_EXTENSION: &str = "segment";
+const TEMP_EXTENSION: &str = "tmp";
+const QUARANTINE_EXTENSION: &str = "corrupt";
+
+/// A batch of log data read back from the spool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpooledBatch {
+    pub key: String,
+    pub payload: String,
+}
+
+/// The outcome of [`Spool::push`] once the batch is stored.
+#[derive(Debug, Default)]
+pub struct Pushed {
+    /// The batches evicted to stay within the size limit, oldest first
+    pub evicted: Vec<SpooledBatch>,
+    /// Failures after the batch was stored, such as evicted segments that could not be read
+    /// back or deleted. They do not affect the stored batch.
+    pub errors: Vec<TracingS3Error>,
+}
+
+#[derive(Debug)]
+struct Segment {
+    seq: u64,
+    size_in_bytes: u64,
+}
+
+/// Local disk spool (write-ahead log) for batches that could not be uploaded to S3.
+///
+/// Each batch is stored in its own segment file, named after a monotonically increasing
+/// sequence number, holding the target object key on the first line and the payload after it.
+/// Segments are replayed oldest first and survive process restarts. When the total size
+/// exceeds the configured limit, the oldest segments are evicted. Segments that cannot be
+/// read back are moved aside with a `.corrupt` extension, see [`Spool::quarantine_front`].
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    max_size_in_bytes: u64,
+    segments: VecDeque<Segment>,
+    size_in_bytes: u64,
+    next_seq: u64,
+    evicted: u64,
+}
+
+impl Spool {
+    /// Opens (creating if needed) a spool directory and recovers segments left by a previous run.
+    ///
+    /// # Arguments
+    /// * `dir` - The spool directory
+    /// * `max_size_in_bytes` - The maximum total size of all segments
+    ///
+    /// # Returns
+    /// * `Ok(Spool)` - If the directory could be created and scanned
+    /// * `Err(TracingS3Error)` - If the directory is not usable
+    pub fn open(dir: impl AsRef<Path>, max_size_in_bytes: u64) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(TEMP_EXTENSION) => {
+                    // A write that never completed; the batch was not acknowledged as spooled.
+                    let _ = fs::remove_file(&path);
+                }
+                Some(SEGMENT_EXTENSION) => {
+                    if let Some(seq) = path
+                        .file_stem()
+                        .and_then(|s| s.to_str())
+                        .and_then(|s| s.parse::<u64>().ok())
+                    {
+                        segments.push(Segment {
+                            seq,
+                            size_in_bytes: fs::metadata(&path)?.len(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        segments.sort_by_key(|segment| segment.seq);
+        let next_seq = segments.last().map_or(0, |segment| segment.seq + 1);
+        let size_in_bytes = segments.iter().map(|segment| segment.size_in_bytes).sum();
+        Ok(Self {
+            dir,
+            max_size_in_bytes,
+            segments: segments.into(),
+            size_in_bytes,
+            next_seq,
+            evicted: 0,
+        })
+    }
+
+    /// Writes a batch to a new segment, then evicts the oldest segments while over the size limit.
+    ///
+    /// The segment is written to a temporary file, synced and renamed, so a crash leaves
+    /// either the whole segment or none of it.
+    ///
+    /// # Arguments
+    /// * `key` - The S3 object key the batch belongs to
+    /// * `payload` - The batch content
+    ///
+    /// # Returns
+    /// * `Ok(Pushed)` - If the batch is stored, with the evicted batches
+    /// * `Err(TracingS3Error)` - If the segment could not be written, or the batch alone is
+    ///   larger than the size limit, in which case nothing is written
+    pub fn push(&mut self, key: &str, payload: &str) -> Result<Pushed> {
+        if key.contains('\n') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Object key must not contain a newline",
+            )
+            .into());
+        }
+        let content = format!("{key}\n{payload}");
+        if content.len() as u64 > self.max_size_in_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Batch of {} bytes does not fit in a spool of {} bytes",
+                    content.len(),
+                    self.max_size_in_bytes
+                ),
+            )
+            .into());
+        }
+        let seq = self.next_seq;
+        let temp_path = self.path(seq, TEMP_EXTENSION);
+        if let Err(err) = write_synced(&temp_path, content.as_bytes())
+            .and_then(|()| fs::rename(&temp_path, self.path(seq, SEGMENT_EXTENSION)))
+        {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err.into());
+        }
+        self.next_seq += 1;
+        self.segments.push_back(Segment {
+            seq,
+            size_in_bytes: content.len() as u64,
+        });
+        self.size_in_bytes += content.len() as u64;
+        // From here on the batch is stored, so failures are reported instead of returned.
+        let mut pushed = Pushed::default();
+        if let Err(err) = sync_dir(&self.dir) {
+            pushed.errors.push(err.into());
+        }
+        while self.size_in_bytes > self.max_size_in_bytes && !self.segments.is_empty() {
+            // A segment that cannot be read back is evicted all the same.
+            match self.front() {
+                Ok(Some(batch)) => pushed.evicted.push(batch),
+                Ok(None) => {}
+                Err(err) => pushed.errors.push(err),
+            }
+            if let Err(err) = self.pop_front() {
+                pushed.errors.push(err);
+            }
+            self.evicted += 1;
+        }
+        Ok(pushed)
+    }
+
+    /// Reads the oldest spooled batch without removing it.
+    pub fn front(&self) -> Result<Option<SpooledBatch>> {
+        let Some(segment) = self.segments.front() else {
+            return Ok(None);
+        };
+        let content = fs::read_to_string(self.path(segment.seq, SEGMENT_EXTENSION))?;
+        let (key, payload) = content.split_once('\n').ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Corrupted spool segment {}", segment.seq),
+            )
+        })?;
+        Ok(Some(SpooledBatch {
+            key: key.to_string(),
+            payload: payload.to_string(),
+        }))
+    }
+
+    /// Moves the oldest segment out of the spool, typically because [`Spool::front`] cannot
+    /// read it, so the segments after it can be replayed. The file is kept next to the
+    /// segments with a `.corrupt` extension for inspection, and is ignored from then on.
+    ///
+    /// # Returns
+    /// * `Ok(Some(PathBuf))` - The new path of the segment
+    /// * `Ok(None)` - If the spool is empty or the segment file no longer exists
+    /// * `Err(TracingS3Error)` - If the file could not be renamed; the segment is removed from
+    ///   the spool all the same
+    pub fn quarantine_front(&mut self) -> Result<Option<PathBuf>> {
+        let Some(segment) = self.segments.pop_front() else {
+            return Ok(None);
+        };
+        self.size_in_bytes -= segment.size_in_bytes;
+        let path = self.path(segment.seq, QUARANTINE_EXTENSION);
+        match fs::rename(self.path(segment.seq, SEGMENT_EXTENSION), &path) {
+            Ok(()) => Ok(Some(path)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Removes the oldest spooled batch, typically after it was uploaded.
+    pub fn pop_front(&mut self) -> Result<()> {
+        if let Some(segment) = self.segments.pop_front() {
+            self.size_in_bytes -= segment.size_in_bytes;
+            match fs::remove_file(self.path(segment.seq, SEGMENT_EXTENSION)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of spooled batches.
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Returns true if nothing is waiting to be replayed.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Returns the total size of all segments in bytes.
+    pub fn size_in_bytes(&self) -> u64 {
+        self.size_in_bytes
+    }
+
+    /// Returns how many segments were evicted to stay within the size limit since opening.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    fn path(&self, seq: u64, extension: &str) -> PathBuf {
+        self.dir.join(format!("{seq:020}.{extension}"))
+    }
+}
+
+/// Writes `content` to a new file and syncs it to disk.
+fn write_synced(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+/// Syncs a directory, so a file renamed into it survives a crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Directories cannot be opened for syncing on this platform.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spool::{Spool, SpooledBatch};
+
+    fn batch(key: &str, payload: &str) -> SpooledBatch {
+        SpooledBatch {
+            key: key.to_string(),
+            payload: payload.to_string(),
+        }
+    }
+
+    #[test]
+    fn replays_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), 1_024).unwrap();
+        spool.push("a", "one\ntwo").unwrap();
+        spool.push("b", "three").unwrap();
+        assert_eq!(spool.len(), 2);
+        assert_eq!(spool.front().unwrap(), Some(batch("a", "one\ntwo")));
+        spool.pop_front().unwrap();
+        assert_eq!(spool.front().unwrap(), Some(batch("b", "three")));
+        spool.pop_front().unwrap();
+        assert_eq!(spool.front().unwrap(), None);
+        assert_eq!(spool.size_in_bytes(), 0);
+    }
+
+    #[test]
+    fn recovers_segments_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut spool = Spool::open(dir.path(), 1_024).unwrap();
+            spool.push("a", "one").unwrap();
+            spool.push("b", "two").unwrap();
+        }
+        std::fs::write(dir.path().join("00000000000000000007.tmp"), "partial").unwrap();
+        let mut spool = Spool::open(dir.path(), 1_024).unwrap();
+        assert_eq!(spool.len(), 2);
+        spool.push("c", "three").unwrap();
+        assert_eq!(spool.front().unwrap(), Some(batch("a", "one")));
+        spool.pop_front().unwrap();
+        spool.pop_front().unwrap();
+        assert_eq!(spool.front().unwrap(), Some(batch("c", "three")));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn evicts_oldest_segments_over_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        // Every segment is "k\n" + 8 bytes = 10 bytes.
+        let mut spool = Spool::open(dir.path(), 25).unwrap();
+        assert!(spool.push("k", "batch-01").unwrap().evicted.is_empty());
+        assert!(spool.push("k", "batch-02").unwrap().evicted.is_empty());
+        let pushed = spool.push("k", "batch-03").unwrap();
+        assert_eq!(pushed.evicted, vec![batch("k", "batch-01")]);
+        assert!(pushed.errors.is_empty(), "{:?}", pushed.errors);
+        assert_eq!(spool.len(), 2);
+        assert_eq!(spool.evicted(), 1);
+        assert!(spool.size_in_bytes() <= 25);
+        assert_eq!(spool.front().unwrap(), Some(batch("k", "batch-02")));
+    }
+
+    #[test]
+    fn quarantines_unreadable_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut spool = Spool::open(dir.path(), 1_024).unwrap();
+            spool.push("a", "one").unwrap();
+            spool.push("b", "two").unwrap();
+        }
+        // A segment truncated by a crash.
+        let truncated = dir.path().join("00000000000000000000.segment");
+        std::fs::write(&truncated, "").unwrap();
+        let mut spool = Spool::open(dir.path(), 1_024).unwrap();
+        assert!(spool.front().is_err());
+        let quarantined = spool.quarantine_front().unwrap().unwrap();
+        assert_eq!(quarantined.extension().unwrap(), "corrupt");
+        assert!(!truncated.exists());
+        assert_eq!(spool.front().unwrap(), Some(batch("b", "two")));
+
+        let spool = Spool::open(dir.path(), 1_024).unwrap();
+        assert_eq!(spool.len(), 1);
+        assert!(quarantined.exists());
+    }
+
+    #[test]
+    fn keeps_the_batch_when_eviction_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), 15).unwrap();
+        spool.push("k", "batch-01").unwrap();
+        // A segment that can neither be read nor deleted.
+        let blocked = dir.path().join("00000000000000000000.segment");
+        std::fs::remove_file(&blocked).unwrap();
+        std::fs::create_dir(&blocked).unwrap();
+        let pushed = spool.push("k", "batch-02").unwrap();
+        assert!(pushed.evicted.is_empty());
+        assert_eq!(pushed.errors.len(), 2, "{:?}", pushed.errors);
+        assert_eq!(spool.len(), 1);
+        assert_eq!(spool.front().unwrap(), Some(batch("k", "batch-02")));
+    }
+
+    #[test]
+    fn rejects_batches_larger_than_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::open(dir.path(), 15).unwrap();
+        spool.push("k", "batch-01").unwrap();
+        assert!(spool.push("k", "a batch that does not fit").is_err());
+        assert_eq!(spool.len(), 1);
+        assert_eq!(spool.evicted(), 0);
+        assert_eq!(spool.front().unwrap(), Some(batch("k", "batch-01")));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,14 +1,35 @@
 #[cfg(test)]
 mod tests {
+    use crate::config::builder::TracingS3ConfigBuilder;
+    use crate::config::compression::Compression;
+    use crate::config::key_template::KeyTemplate;
+    use crate::config::rotation::Rotation;
     use crate::config::tracing_s3_config::TracingS3Config;
     use crate::config::types::{
         Bucket, BufferSizeLimitKb, CronIntervalInMs, Endpoint, ObjectSizeLimitMb, Postfix, Prefix,
     };
-    use crate::layer::http_log_layer::HttpLogLayer;
+    use crate::config::worker_runtime::WorkerRuntime;
+    use crate::layer::event_queue::OverflowPolicy;
+    use crate::layer::http_log_layer::{HttpLogLayer, Output};
+    use crate::sink::LogSink;
+    use crate::sink::memory_sink::MemorySink;
+    use crate::sink::s3_sink::UploadStrategy;
+    use crate::spool::Spool;
+    use crate::testing::fake_s3::{self, FakeS3};
+    use chrono::{DateTime, Utc};
+    use chrono_tz::Tz;
+    use flate2::read::MultiGzDecoder;
+    use serde_json::{Value, json};
+    use std::collections::HashSet;
+    use std::io::Read;
     use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::time::Duration;
-    use tracing::Dispatch;
+    use tokio::sync::RwLock;
     use tracing::dispatcher::with_default;
+    use tracing::{Dispatch, Level};
+    use tracing_subscriber::EnvFilter;
+    use tracing_subscriber::fmt::format::FmtSpan;
     use tracing_subscriber::layer::SubscriberExt;
 
     #[tracing::instrument(name = "add", skip_all)]
@@ -47,7 +68,7 @@ mod tests {
         )
         .await
         .unwrap();
-        let http_log_layer = HttpLogLayer::new(Arc::new(config));
+        let (http_log_layer, _guard) = HttpLogLayer::new(Arc::new(config)).unwrap();
         let subscriber = tracing_subscriber::registry()
             .with(
                 tracing_subscriber::EnvFilter::try_from_default_env()
@@ -67,4 +88,1793 @@ mod tests {
         });
         tokio::time::sleep(Duration::from_millis(10_000)).await;
     }
+
+    fn emit_events<K: LogSink>(http_log_layer: HttpLogLayer<K>, count: u64) {
+        let subscriber = tracing_subscriber::registry().with(http_log_layer);
+        with_default(&Dispatch::new(subscriber), || {
+            for i in 0..count {
+                tracing::info!(i, "shutdown test");
+            }
+        });
+    }
+
+    /// Returns a builder for layers writing to a [`MemorySink`]. The S3 client is never used,
+    /// and the 60 second flush interval leaves uploads to the test.
+    fn memory_config() -> TracingS3ConfigBuilder {
+        TracingS3Config::builder()
+            .bucket("memory-bucket")
+            .access_key_id("test")
+            .secret_access_key("test")
+            .cron_interval_in_ms(60_000)
+    }
+
+    #[tokio::test]
+    async fn shutdown_flushes_pending_events() {
+        let fake = FakeS3::start().await;
+        let config = fake
+            .builder()
+            .cron_interval_in_ms(60_000)
+            .build()
+            .await
+            .unwrap();
+        let (http_log_layer, guard) = HttpLogLayer::new(Arc::new(config)).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        emit_events(http_log_layer, 100);
+        guard.shutdown().await.unwrap();
+        let object = String::from_utf8(fake.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 100);
+        assert!(object.contains("\"i\":99"));
+    }
+
+    #[tokio::test]
+    async fn builder_accepts_an_existing_client() {
+        let fake = FakeS3::start().await;
+        let config = TracingS3Config::builder()
+            .client(fake.client())
+            .bucket(fake_s3::BUCKET)
+            .upload_strategy(UploadStrategy::Append)
+            .cron_interval_in_ms(60_000)
+            .build()
+            .await
+            .unwrap();
+        let (http_log_layer, guard) = HttpLogLayer::new(Arc::new(config)).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        emit_events(http_log_layer, 10);
+        guard.shutdown().await.unwrap();
+        let object = String::from_utf8(fake.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 10);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn dropping_guard_flushes_pending_events() {
+        let fake = FakeS3::start().await;
+        let config = fake
+            .builder()
+            .cron_interval_in_ms(60_000)
+            .build()
+            .await
+            .unwrap();
+        let (http_log_layer, guard) = HttpLogLayer::new(Arc::new(config)).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        emit_events(http_log_layer, 10);
+        drop(guard);
+        let object = String::from_utf8(fake.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 10);
+    }
+
+    #[tokio::test]
+    async fn send_logs_keeps_payload_when_retries_are_exhausted() {
+        let fake = FakeS3::start().await;
+        let config = Arc::new(fake.builder().build().await.unwrap());
+        let output = Arc::new(RwLock::new(Output::new("prefix", "log")));
+        output
+            .read()
+            .await
+            .append_to_buffer(Level::INFO, "first".to_string())
+            .await;
+        for _ in 0..3 {
+            fake.fail_next(503, "SlowDown");
+        }
+        assert!(
+            HttpLogLayer::send_logs(config.clone(), output.clone(), &fake.sink())
+                .await
+                .is_err()
+        );
+        output
+            .read()
+            .await
+            .append_to_buffer(Level::INFO, "second".to_string())
+            .await;
+        HttpLogLayer::send_logs(config, output.clone(), &fake.sink())
+            .await
+            .unwrap();
+        let name = output.read().await.name();
+        assert_eq!(fake.object(&name).unwrap(), b"first\nsecond\n");
+    }
+
+    #[tokio::test]
+    async fn spooled_batches_are_replayed_in_order_after_an_outage() {
+        let fake = FakeS3::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = fake.builder().spool(dir.path(), 1).build().await.unwrap();
+        let config = Arc::new(config);
+        let output = Arc::new(RwLock::new(Output::new("prefix", "log")));
+        let name = output.read().await.name();
+        for batch in ["first", "second"] {
+            output
+                .read()
+                .await
+                .append_to_buffer(Level::INFO, batch.to_string())
+                .await;
+            for _ in 0..3 {
+                fake.fail_next(503, "SlowDown");
+            }
+            assert!(
+                HttpLogLayer::send_logs(config.clone(), output.clone(), &fake.sink())
+                    .await
+                    .is_err()
+            );
+        }
+        assert_eq!(config.spool.as_ref().unwrap().lock().unwrap().len(), 2);
+        assert!(fake.object(&name).is_none());
+
+        // A restarted process picks up the segments from the same directory.
+        let restarted = fake.builder().spool(dir.path(), 1).build().await.unwrap();
+        let restarted = Arc::new(restarted);
+        output
+            .read()
+            .await
+            .append_to_buffer(Level::INFO, "third".to_string())
+            .await;
+        HttpLogLayer::send_logs(restarted.clone(), output.clone(), &fake.sink())
+            .await
+            .unwrap();
+        assert!(restarted.spool.as_ref().unwrap().lock().unwrap().is_empty());
+        assert_eq!(fake.object(&name).unwrap(), b"first\nsecond\nthird\n");
+    }
+
+    #[tokio::test]
+    async fn unreadable_spool_segments_do_not_block_the_replay() {
+        let fake = FakeS3::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        let output = Arc::new(RwLock::new(Output::new("prefix", "log")));
+        let name = output.read().await.name();
+        {
+            let mut spool = Spool::open(dir.path(), 1_024).unwrap();
+            spool.push(&name, "first\n").unwrap();
+            spool.push(&name, "second\n").unwrap();
+        }
+        // The first segment was truncated by a crash.
+        std::fs::write(dir.path().join("00000000000000000000.segment"), "").unwrap();
+        let config = fake.builder().spool(dir.path(), 1).build().await.unwrap();
+        let config = Arc::new(config);
+        for _ in 0..2 {
+            HttpLogLayer::send_logs(config.clone(), output.clone(), &fake.sink())
+                .await
+                .unwrap();
+        }
+        assert!(config.spool.as_ref().unwrap().lock().unwrap().is_empty());
+        let object = String::from_utf8(fake.object(&name).unwrap()).unwrap();
+        assert!(object.starts_with("second\n"), "{object}");
+        assert!(object.contains("Unreadable spool segment"), "{object}");
+        assert!(dir.path().join("00000000000000000000.corrupt").exists());
+    }
+
+    #[tokio::test]
+    async fn evicted_spool_batches_are_recorded_as_dropped() {
+        let fake = FakeS3::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = fake.builder().spool(dir.path(), 1).build().await.unwrap();
+        let config = Arc::new(config);
+        let output = Arc::new(RwLock::new(Output::new("prefix", "log")));
+        let name = output.read().await.name();
+        // Each batch takes up more than half of the spool, so the second evicts the first.
+        for batch in ["first", "second"] {
+            output
+                .read()
+                .await
+                .append_to_buffer(Level::INFO, batch.repeat(120_000))
+                .await;
+            for _ in 0..3 {
+                fake.fail_next(503, "SlowDown");
+            }
+            assert!(
+                HttpLogLayer::send_logs(config.clone(), output.clone(), &fake.sink())
+                    .await
+                    .is_err()
+            );
+        }
+        assert_eq!(config.spool.as_ref().unwrap().lock().unwrap().len(), 1);
+        HttpLogLayer::send_logs(config.clone(), output.clone(), &fake.sink())
+            .await
+            .unwrap();
+        let object = String::from_utf8(fake.object(&name).unwrap()).unwrap();
+        let lines: Vec<_> = object.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("second"));
+        assert!(lines[1].contains("\"1 events dropped\""));
+    }
+
+    #[tokio::test]
+    async fn bounded_channel_records_dropped_events() {
+        let fake = FakeS3::start().await;
+        let config = fake
+            .builder()
+            .channel_capacity(5)
+            .overflow_policy(OverflowPolicy::DropNewest)
+            .cron_interval_in_ms(60_000)
+            .build()
+            .await
+            .unwrap();
+        let (http_log_layer, guard) = HttpLogLayer::new(Arc::new(config)).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let events = http_log_layer.events.clone();
+        // The current-thread runtime cannot run the worker while we emit, so the queue fills up.
+        emit_events(http_log_layer, 50);
+        assert_eq!(events.dropped_total(), 45);
+        guard.shutdown().await.unwrap();
+        let object = String::from_utf8(fake.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 6);
+        assert_eq!(
+            object
+                .lines()
+                .filter(|line| line.contains("\"45 events dropped\""))
+                .count(),
+            1
+        );
+        assert!(object.contains("\"i\":4"));
+        assert!(!object.contains("\"i\":5"));
+    }
+
+    #[tokio::test]
+    async fn layer_writes_through_custom_sink() {
+        let fake = FakeS3::start().await;
+        let config = fake
+            .builder()
+            .cron_interval_in_ms(60_000)
+            .build()
+            .await
+            .unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        emit_events(http_log_layer, 3);
+        guard.shutdown().await.unwrap();
+        let object = String::from_utf8(sink.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 3);
+        assert_eq!(sink.appends(), 1);
+        assert!(fake.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn shutdown_reports_upload_errors() {
+        let fake = FakeS3::start().await;
+        let config = fake
+            .builder()
+            .cron_interval_in_ms(60_000)
+            .build()
+            .await
+            .unwrap();
+        let (http_log_layer, guard) = HttpLogLayer::new(Arc::new(config)).unwrap();
+        emit_events(http_log_layer, 1);
+        fake.fail_next(404, "NoSuchKey");
+        fake.fail_next(403, "AccessDenied");
+        assert!(guard.shutdown().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn objects_rotate_at_the_object_size_limit() {
+        let config = memory_config()
+            .buffer_size_limit_kb(1)
+            .object_size_limit_mb(1)
+            .build()
+            .await
+            .unwrap();
+        let config = Arc::new(config);
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(config.clone(), sink.clone()).unwrap();
+        let output = http_log_layer.output.clone();
+        let first = output.read().await.name();
+
+        for _ in 0..2 {
+            output
+                .read()
+                .await
+                .append_to_buffer(Level::INFO, "a".repeat(2_000))
+                .await;
+            HttpLogLayer::send_logs(config.clone(), output.clone(), &sink)
+                .await
+                .unwrap();
+        }
+        assert_eq!(output.read().await.name(), first);
+        assert!(sink.finalized().is_empty());
+
+        output
+            .read()
+            .await
+            .append_to_buffer(Level::INFO, "b".repeat(1_024 * 1_024))
+            .await;
+        HttpLogLayer::send_logs(config.clone(), output.clone(), &sink)
+            .await
+            .unwrap();
+        let second = output.read().await.name();
+        assert_ne!(second, first);
+        assert_eq!(sink.finalized(), vec![first.clone()]);
+        assert_eq!(sink.object(&first).unwrap().len(), 4_003 + 1_024 * 1_024);
+
+        output
+            .read()
+            .await
+            .append_to_buffer(Level::INFO, "c".to_string())
+            .await;
+        HttpLogLayer::send_logs(config, output, &sink)
+            .await
+            .unwrap();
+        assert_eq!(sink.object(&second).as_deref(), Some(&b"c\n"[..]));
+        guard.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn full_buffer_is_flushed_before_the_next_tick() {
+        let config = memory_config()
+            .buffer_size_limit_kb(1)
+            .build()
+            .await
+            .unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(http_log_layer));
+        with_default(&dispatch, || tracing::info!("below the buffer limit"));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(sink.appends(), 0);
+
+        with_default(&dispatch, || {
+            for i in 0..20 {
+                tracing::info!(i, "filling the buffer");
+            }
+        });
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while sink.appends() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let object = String::from_utf8(sink.object(&name).unwrap()).unwrap();
+        assert!(object.contains("below the buffer limit"));
+        guard.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn errors_are_flushed_immediately_and_debounced() {
+        let config = memory_config()
+            .flush_on_level(Level::ERROR)
+            .flush_debounce_in_ms(300)
+            .build()
+            .await
+            .unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(http_log_layer));
+        let wait_for_appends = |appends: usize| {
+            let sink = sink.clone();
+            tokio::time::timeout(Duration::from_secs(5), async move {
+                while sink.appends() < appends {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+        };
+
+        with_default(&dispatch, || tracing::warn!("below the flush level"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(sink.appends(), 0);
+
+        with_default(&dispatch, || tracing::error!("first error"));
+        wait_for_appends(1).await.unwrap();
+        let object = String::from_utf8(sink.object(&name).unwrap()).unwrap();
+        assert!(object.contains("below the flush level"));
+        assert!(object.contains("first error"));
+
+        with_default(&dispatch, || {
+            for i in 0..50 {
+                tracing::error!(i, "error storm");
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(sink.appends(), 1);
+        wait_for_appends(2).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(sink.appends(), 2);
+        let object = String::from_utf8(sink.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 52);
+        guard.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn flush_uploads_every_event_emitted_before_the_call() {
+        let config = memory_config().build().await.unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let flush_handle = http_log_layer.flush_handle();
+        emit_events(http_log_layer, 25);
+        flush_handle.clone().flush().await.unwrap();
+        let object = String::from_utf8(sink.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 25);
+        assert!(sink.finalized().is_empty());
+
+        flush_handle.flush().await.unwrap();
+        assert_eq!(sink.appends(), 1);
+
+        guard.shutdown().await.unwrap();
+        assert_eq!(sink.finalized(), vec![name]);
+        assert!(flush_handle.flush().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn flush_reports_upload_errors() {
+        let config = memory_config().build().await.unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let flush_handle = guard.flush_handle();
+        emit_events(http_log_layer, 3);
+        sink.fail_next(true);
+        assert!(flush_handle.flush().await.is_err());
+        flush_handle.flush().await.unwrap();
+        let object = String::from_utf8(sink.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 3);
+        guard.shutdown().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn flush_blocking_waits_for_the_upload() {
+        let config = memory_config().build().await.unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let flush_handle = http_log_layer.flush_handle();
+        emit_events(http_log_layer, 10);
+        flush_handle.flush_blocking(Duration::from_secs(5)).unwrap();
+        let object = String::from_utf8(sink.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 10);
+        guard.shutdown().await.unwrap();
+    }
+
+    /// Builds a [`memory_config`] outside of any runtime.
+    fn config_without_runtime(worker_runtime: WorkerRuntime) -> TracingS3Config {
+        memory_config()
+            .worker_runtime(worker_runtime)
+            .build_blocking()
+            .unwrap()
+    }
+
+    #[test]
+    fn dedicated_thread_worker_runs_without_a_runtime() {
+        let config = config_without_runtime(WorkerRuntime::DedicatedThread);
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.blocking_read().name();
+        let flush_handle = http_log_layer.flush_handle();
+        emit_events(http_log_layer, 10);
+        flush_handle.flush_blocking(Duration::from_secs(5)).unwrap();
+        let object = String::from_utf8(sink.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 10);
+
+        drop(guard);
+        assert!(sink.finalized().contains(&name));
+        assert!(flush_handle.flush_blocking(Duration::from_secs(5)).is_err());
+    }
+
+    #[test]
+    fn worker_runs_on_the_given_runtime_handle() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let config = config_without_runtime(WorkerRuntime::Handle(runtime.handle().clone()));
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.blocking_read().name();
+        emit_events(http_log_layer, 10);
+        drop(guard);
+        let object = String::from_utf8(sink.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 10);
+    }
+
+    #[test]
+    fn ambient_worker_requires_a_runtime() {
+        let config = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(memory_config().build())
+            .unwrap();
+        let Err(err) = HttpLogLayer::with_sink(Arc::new(config), MemorySink::new()) else {
+            panic!("created a layer without a runtime");
+        };
+        assert_eq!(err.field(), Some("worker_runtime"), "{err}");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn buffer_swap_is_lossless_under_concurrent_logging() {
+        const PRODUCERS: usize = 8;
+        const EVENTS_PER_PRODUCER: usize = 5_000;
+        let output = Arc::new(Output::new("prefix", "log"));
+        let producers_done = Arc::new(AtomicBool::new(false));
+        let flusher = {
+            let output = output.clone();
+            let producers_done = producers_done.clone();
+            tokio::spawn(async move {
+                let mut flushed = Vec::new();
+                while !producers_done.load(Ordering::SeqCst) {
+                    for batch in output.flush_buffer().await {
+                        flushed.extend(batch.payload.split('\n').map(str::to_string));
+                    }
+                    tokio::task::yield_now().await;
+                }
+                flushed
+            })
+        };
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|producer| {
+                let output = output.clone();
+                tokio::spawn(async move {
+                    for i in 0..EVENTS_PER_PRODUCER {
+                        output
+                            .append_to_buffer(Level::INFO, format!("{producer}-{i}"))
+                            .await;
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.await.unwrap();
+        }
+        producers_done.store(true, Ordering::SeqCst);
+        let mut flushed = flusher.await.unwrap();
+
+        let remaining = output.flush_buffer().await;
+        let remaining_lines: Vec<_> = remaining
+            .iter()
+            .flat_map(|batch| batch.payload.split('\n'))
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect();
+        assert_eq!(output.size_in_bytes(), 0);
+        assert_eq!(output.buffer_len().await, 0);
+        flushed.extend(remaining_lines);
+
+        let mut seen = HashSet::new();
+        for line in flushed.iter().filter(|line| !line.is_empty()) {
+            assert!(seen.insert(line.clone()), "duplicated entry {line}");
+        }
+        assert_eq!(seen.len(), PRODUCERS * EVENTS_PER_PRODUCER);
+    }
+
+    #[tokio::test]
+    async fn size_counter_matches_buffered_bytes() {
+        let output = Output::new("prefix", "log");
+        output
+            .append_to_buffer(Level::INFO, "abc".to_string())
+            .await;
+        output.append_to_buffer(Level::INFO, "de".to_string()).await;
+        assert_eq!(output.size_in_bytes(), 7);
+        let batches = output.flush_buffer().await;
+        assert_eq!(output.size_in_bytes(), 0);
+        output.append_to_buffer(Level::INFO, "f".to_string()).await;
+        output.requeue(batches.into_iter().next().unwrap()).await;
+        assert_eq!(output.size_in_bytes(), "f\nabc\nde\n".len() as u64);
+    }
+
+    #[tokio::test]
+    async fn requeued_batches_keep_their_key() {
+        let mut output = Output::new("prefix", "log");
+        let first = output.name();
+        output
+            .append_to_buffer(Level::INFO, "abc".to_string())
+            .await;
+        let batch = output.flush_buffer().await.into_iter().next().unwrap();
+        output.bump_part();
+        output.requeue(batch).await;
+        output.append_to_buffer(Level::INFO, "de".to_string()).await;
+        let batches: Vec<_> = output
+            .flush_buffer()
+            .await
+            .into_iter()
+            .map(|batch| (batch.key, batch.payload))
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                (first, "abc\n".to_string()),
+                (output.name(), "de\n".to_string())
+            ]
+        );
+    }
+
+    /// Returns the fixed instant the rotation tests start at.
+    fn start_time() -> DateTime<Utc> {
+        "2024-01-02T11:00:00Z".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn output_rotates_when_the_window_changes() {
+        let now = start_time();
+        let mut output = Output::new("prefix", "log")
+            .with_rotation(Rotation::Hourly, Tz::UTC)
+            .with_window_start(now);
+        let first = output.name();
+        assert!(first.starts_with("2024-01-02/0/"));
+        output
+            .append_to_buffer(Level::INFO, "before".to_string())
+            .await;
+        assert!(
+            !output
+                .rotate_if_due(now + chrono::Duration::minutes(59))
+                .await
+        );
+        assert!(
+            output
+                .rotate_if_due(now + chrono::Duration::hours(25))
+                .await
+        );
+        let second = output.name();
+        assert!(second.starts_with("2024-01-03/1/"));
+        assert_eq!(output.take_rotated(), vec![first.clone()]);
+        output
+            .append_to_buffer(Level::INFO, "after".to_string())
+            .await;
+        let batches: Vec<_> = output
+            .flush_buffer()
+            .await
+            .into_iter()
+            .map(|batch| (batch.key, batch.payload))
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                (first, "before\n".to_string()),
+                (second, "after\n".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_written_objects_are_finalized_on_rotation() {
+        let now = start_time();
+        let mut output = Output::new("prefix", "log")
+            .with_rotation(Rotation::Hourly, Tz::UTC)
+            .with_window_start(now)
+            .with_key_template(KeyTemplate::new("{level}/{part}.log").unwrap(), None);
+        assert!(output.rotate_if_due(now + chrono::Duration::hours(1)).await);
+        assert!(output.take_rotated().is_empty());
+
+        let info = output.name_for(Level::INFO);
+        output
+            .append_to_buffer(Level::INFO, "written".to_string())
+            .await;
+        assert_eq!(output.written_names(), vec![info.clone()]);
+        assert!(output.rotate_if_due(now + chrono::Duration::hours(2)).await);
+        assert_eq!(output.take_rotated(), vec![info]);
+        output.flush_buffer().await;
+        output.bump_part();
+        assert!(output.take_rotated().is_empty());
+    }
+
+    #[test]
+    fn object_dates_follow_the_configured_timezone() {
+        for (timezone, date) in [
+            (Tz::Pacific__Kiritimati, "2024-01-03"),
+            (Tz::Etc__GMTPlus12, "2024-01-01"),
+        ] {
+            let output = Output::new("prefix", "log")
+                .with_rotation(Rotation::Daily, timezone)
+                .with_window_start(start_time());
+            assert!(output.name().starts_with(date));
+        }
+    }
+
+    /// Sleeps until shortly after the next boundary of a rotation window of `window_ms`
+    /// milliseconds aligned to the Unix epoch, so a test has almost a full window before the
+    /// next rotation.
+    async fn sleep_past_window_boundary(window_ms: i64) {
+        let now_ms = Utc::now().timestamp_millis();
+        let wait_ms = window_ms - now_ms.rem_euclid(window_ms) + 10;
+        tokio::time::sleep(Duration::from_millis(wait_ms as u64)).await;
+    }
+
+    #[tokio::test]
+    async fn events_after_a_window_boundary_go_to_the_next_object() {
+        // No tick runs during the test, so only the events themselves trigger the rotation.
+        let config = memory_config()
+            .rotation(Rotation::custom(Duration::from_millis(200)).unwrap())
+            .build()
+            .await
+            .unwrap();
+        let sink = MemorySink::new();
+        // Create the layer and log the first event within one window.
+        sleep_past_window_boundary(200).await;
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let flush_handle = http_log_layer.flush_handle();
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(http_log_layer));
+        with_default(&dispatch, || tracing::info!("first window"));
+        flush_handle.flush().await.unwrap();
+        sleep_past_window_boundary(200).await;
+        with_default(&dispatch, || tracing::info!("second window"));
+        flush_handle.flush().await.unwrap();
+        guard.shutdown().await.unwrap();
+
+        let objects = sink.objects();
+        assert_eq!(objects.len(), 2, "{objects:?}");
+        let first = String::from_utf8(objects[0].1.clone()).unwrap();
+        let second = String::from_utf8(objects[1].1.clone()).unwrap();
+        assert!(first.contains("first window") && !first.contains("second window"));
+        assert!(second.contains("second window") && !second.contains("first window"));
+        assert_eq!(sink.finalized().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn objects_rotate_when_the_time_window_changes() {
+        let config = memory_config()
+            .cron_interval_in_ms(20)
+            .rotation(Rotation::custom(Duration::from_millis(200)).unwrap())
+            .build()
+            .await
+            .unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let output = http_log_layer.output.clone();
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(http_log_layer));
+        let next_window = |name: String| {
+            let output = output.clone();
+            tokio::time::timeout(Duration::from_secs(5), async move {
+                while output.read().await.name() == name {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            })
+        };
+        // Start right after a rotation, far away from the next window boundary.
+        let initial = output.read().await.name();
+        next_window(initial).await.unwrap();
+        let first = output.read().await.name();
+        with_default(&dispatch, || tracing::info!("first window"));
+        next_window(first.clone()).await.unwrap();
+        with_default(&dispatch, || tracing::info!("second window"));
+        let second = output.read().await.name();
+        drop(dispatch);
+        guard.shutdown().await.unwrap();
+
+        let first_object = String::from_utf8(sink.object(&first).unwrap()).unwrap();
+        assert!(first_object.contains("first window"));
+        assert!(!first_object.contains("second window"));
+        let second_object = String::from_utf8(sink.object(&second).unwrap()).unwrap();
+        assert!(second_object.contains("second window"));
+        assert!(sink.finalized().contains(&first));
+    }
+
+    #[tokio::test]
+    async fn level_placeholder_splits_objects_by_level() {
+        let config = memory_config()
+            .service("api")
+            .key_template("{service}/{level}/{part}-{nonce}.log")
+            .build()
+            .await
+            .unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let info = http_log_layer.output.read().await.name_for(Level::INFO);
+        let error = http_log_layer.output.read().await.name_for(Level::ERROR);
+        assert!(info.starts_with("api/info/0-"));
+        assert!(error.starts_with("api/error/0-"));
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(http_log_layer));
+        with_default(&dispatch, || {
+            tracing::info!("first info");
+            tracing::error!("an error");
+            tracing::info!("second info");
+        });
+        guard.shutdown().await.unwrap();
+
+        let info_object = String::from_utf8(sink.object(&info).unwrap()).unwrap();
+        assert_eq!(info_object.lines().count(), 2);
+        assert!(info_object.contains("first info") && info_object.contains("second info"));
+        let error_object = String::from_utf8(sink.object(&error).unwrap()).unwrap();
+        assert_eq!(error_object.lines().count(), 1);
+        assert!(error_object.contains("an error"));
+        assert_eq!(sink.objects().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn service_placeholder_requires_a_service() {
+        let fake = FakeS3::start().await;
+        let template = "{service}/{part}.log";
+        assert!(fake.builder().key_template(template).build().await.is_err());
+        let config = fake
+            .builder()
+            .service("api")
+            .key_template(template)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(config.service.as_deref(), Some("api"));
+    }
+
+    #[tokio::test]
+    async fn objects_built_from_many_flushes_are_valid_ndjson() {
+        let fake = FakeS3::start().await;
+        let config = fake
+            .builder()
+            .cron_interval_in_ms(20)
+            .build()
+            .await
+            .unwrap();
+        let (http_log_layer, guard) = HttpLogLayer::new(Arc::new(config)).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(http_log_layer));
+        for flush in 0..3 {
+            with_default(&dispatch, || {
+                for i in 0..5 {
+                    tracing::info!(flush, i, "framed");
+                }
+            });
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        guard.shutdown().await.unwrap();
+
+        let appends = fake
+            .requests()
+            .iter()
+            .filter(|r| r.method == "PUT" && r.key == name)
+            .count();
+        assert!(appends >= 3);
+        let object = String::from_utf8(fake.object(&name).unwrap()).unwrap();
+        assert!(object.ends_with('\n'));
+        let records: Vec<Value> = object
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 15);
+        assert!(
+            records
+                .iter()
+                .all(|record| record["event"]["message"] == "framed")
+        );
+    }
+
+    #[tokio::test]
+    async fn record_separator_is_configurable() {
+        assert!(memory_config().record_separator("").build().await.is_err());
+        let config = memory_config()
+            .record_separator("\r\n")
+            .build()
+            .await
+            .unwrap();
+        let config = Arc::new(config);
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(config.clone(), sink.clone()).unwrap();
+        let output = http_log_layer.output.clone();
+        for line in ["first", "second"] {
+            output
+                .read()
+                .await
+                .append_to_buffer(Level::INFO, line.to_string())
+                .await;
+            HttpLogLayer::send_logs(config.clone(), output.clone(), &sink)
+                .await
+                .unwrap();
+        }
+        guard.shutdown().await.unwrap();
+        let name = output.read().await.name();
+        assert_eq!(sink.object(&name).unwrap(), b"first\r\nsecond\r\n");
+    }
+
+    #[tokio::test]
+    async fn records_carry_the_span_stack() {
+        let config = memory_config()
+            .current_span(true)
+            .span_list(true)
+            .build()
+            .await
+            .unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(http_log_layer));
+        with_default(&dispatch, || {
+            tracing::info!("outside");
+            let request = tracing::info_span!("request", request_id = 42);
+            let _request = request.enter();
+            let handler = tracing::info_span!("handler", user = tracing::field::Empty);
+            handler.record("user", "alice");
+            let _handler = handler.enter();
+            tracing::info!("inside");
+        });
+        guard.shutdown().await.unwrap();
+
+        let object = String::from_utf8(sink.object(&name).unwrap()).unwrap();
+        let records: Vec<Value> = object
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let outside = &records[0];
+        assert!(outside.get("span").is_none() && outside.get("spans").is_none());
+        let inside = records
+            .iter()
+            .find(|record| record["event"]["message"] == "inside")
+            .unwrap();
+        assert_eq!(inside["span"]["name"], "handler");
+        assert_eq!(inside["span"]["user"], "alice");
+        assert_eq!(inside["spans"][0]["name"], "request");
+        assert_eq!(inside["spans"][0]["request_id"], 42);
+        assert_eq!(inside["spans"][1]["name"], "handler");
+    }
+
+    /// Emits one event outside any span and one inside `outer` > `inner`, and returns the
+    /// uploaded records.
+    async fn trace_context_records(
+        subscriber: impl Fn(HttpLogLayer<MemorySink>) -> Dispatch,
+    ) -> Vec<Value> {
+        let config = memory_config().trace_context(true).build().await.unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let dispatch = subscriber(http_log_layer);
+        with_default(&dispatch, || {
+            tracing::info!("outside");
+            tracing::info_span!("outer").in_scope(|| {
+                tracing::info_span!("inner").in_scope(|| tracing::info!("inside"));
+            });
+        });
+        drop(dispatch);
+        guard.shutdown().await.unwrap();
+        String::from_utf8(sink.object(&name).unwrap())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn trace_context_falls_back_to_random_trace_ids() {
+        let records = trace_context_records(|layer| {
+            Dispatch::new(tracing_subscriber::registry().with(layer))
+        })
+        .await;
+        assert!(records[0].get("trace_id").is_none());
+        let inside = &records[1];
+        let trace_id = inside["trace_id"].as_str().unwrap();
+        let span_id = inside["span_id"].as_str().unwrap();
+        assert_eq!(trace_id.len(), 32);
+        assert_eq!(span_id.len(), 16);
+        assert_ne!(u128::from_str_radix(trace_id, 16).unwrap(), 0);
+        assert_ne!(
+            trace_id.trim_start_matches('0'),
+            span_id.trim_start_matches('0')
+        );
+        assert_eq!(inside["trace_flags"], "00");
+        // The close events of both spans belong to the same trace.
+        assert!(
+            records[2..]
+                .iter()
+                .all(|record| record["trace_id"] == trace_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn fallback_trace_ids_differ_between_root_spans() {
+        let config = memory_config().trace_context(true).build().await.unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(http_log_layer));
+        let mut root_ids = Vec::new();
+        with_default(&dispatch, || {
+            for _ in 0..2 {
+                let root = tracing::info_span!("root");
+                root_ids.push(json!(format!("{:032x}", root.id().unwrap().into_u64())));
+                root.in_scope(|| tracing::info!("inside"));
+            }
+        });
+        drop(dispatch);
+        guard.shutdown().await.unwrap();
+        let trace_ids: Vec<_> = String::from_utf8(sink.object(&name).unwrap())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .filter(|record| record["event"]["message"] == "inside")
+            .map(|record| record["trace_id"].clone())
+            .collect();
+        assert_eq!(trace_ids.len(), 2);
+        assert_ne!(trace_ids[0], trace_ids[1]);
+        // Not derived from the span IDs, which the registry reuses once spans close.
+        assert!(
+            trace_ids
+                .iter()
+                .all(|trace_id| !root_ids.contains(trace_id))
+        );
+    }
+
+    #[cfg(feature = "opentelemetry")]
+    #[tokio::test]
+    async fn trace_context_uses_opentelemetry_ids() {
+        use opentelemetry::trace::TracerProvider;
+        use opentelemetry_sdk::trace::SdkTracerProvider;
+
+        let provider = SdkTracerProvider::builder().build();
+        let tracer = provider.tracer("test");
+        let records = trace_context_records(|layer| {
+            Dispatch::new(
+                tracing_subscriber::registry()
+                    .with(tracing_opentelemetry::layer().with_tracer(tracer.clone()))
+                    .with(layer),
+            )
+        })
+        .await;
+        let inside = &records[1];
+        let trace_id = inside["trace_id"].as_str().unwrap();
+        assert_eq!(trace_id.len(), 32);
+        assert_eq!(inside["span_id"].as_str().unwrap().len(), 16);
+        assert_eq!(inside["trace_flags"], "01");
+        let close_inner = &records[2];
+        assert_eq!(close_inner["trace_id"], trace_id);
+        assert_eq!(close_inner["span_id"], inside["span_id"]);
+    }
+
+    /// Records one span entered twice and returns the messages of the uploaded records.
+    async fn span_event_records(span_events: FmtSpan) -> Vec<Value> {
+        let config = memory_config()
+            .span_events(span_events)
+            .build()
+            .await
+            .unwrap();
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(http_log_layer));
+        with_default(&dispatch, || {
+            let span = tracing::info_span!("work");
+            span.in_scope(|| std::thread::sleep(Duration::from_millis(2)));
+            span.in_scope(|| {});
+        });
+        guard.shutdown().await.unwrap();
+        sink.object(&name)
+            .map(|object| {
+                String::from_utf8(object)
+                    .unwrap()
+                    .lines()
+                    .map(|line| serde_json::from_str(line).unwrap())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn messages(records: &[Value]) -> Vec<&str> {
+        records
+            .iter()
+            .map(|record| record["event"]["message"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn span_events_follow_the_configured_mode() {
+        let cases = [
+            (FmtSpan::NONE, vec![]),
+            (FmtSpan::NEW, vec!["new"]),
+            (FmtSpan::ENTER, vec!["enter", "enter"]),
+            (FmtSpan::EXIT, vec!["exit", "exit"]),
+            (FmtSpan::ACTIVE, vec!["enter", "exit", "enter", "exit"]),
+            (FmtSpan::CLOSE, vec!["close"]),
+            (
+                FmtSpan::FULL,
+                vec!["new", "enter", "exit", "enter", "exit", "close"],
+            ),
+        ];
+        for (span_events, expected) in cases {
+            let records = span_event_records(span_events.clone()).await;
+            assert_eq!(messages(&records), expected, "{span_events:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn close_events_carry_busy_and_idle_nanoseconds() {
+        let records = span_event_records(FmtSpan::CLOSE).await;
+        let close = &records[0]["event"];
+        assert_eq!(close["message"], "close");
+        let busy = close["time.busy"].as_u64().unwrap();
+        let idle = close["time.idle"].as_u64().unwrap();
+        assert!(busy >= 2_000_000, "busy {busy}ns");
+        assert!(idle > 0);
+    }
+
+    #[tokio::test]
+    async fn verbose_env_filter_does_not_amplify_uploads() {
+        let fake = FakeS3::start().await;
+        let config = fake
+            .builder()
+            .cron_interval_in_ms(20)
+            .build()
+            .await
+            .unwrap();
+        let (http_log_layer, guard) = HttpLogLayer::new(Arc::new(config)).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let dispatch = Dispatch::new(
+            tracing_subscriber::registry()
+                .with(EnvFilter::new("trace"))
+                .with(http_log_layer),
+        );
+        // The worker and the SDK's connection tasks run on this thread, under the same subscriber.
+        let default_guard = tracing::dispatcher::set_default(&dispatch);
+        for i in 0..5 {
+            tracing::info!(i, "application event");
+            tokio::time::sleep(Duration::from_millis(60)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        guard.shutdown().await.unwrap();
+        drop(default_guard);
+
+        let object = String::from_utf8(fake.object(&name).unwrap()).unwrap();
+        let records: Vec<Value> = object
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 5, "{object}");
+        assert!(
+            records
+                .iter()
+                .all(|record| record["event"]["message"] == "application event")
+        );
+    }
+
+    #[tokio::test]
+    async fn gzip_objects_decompress_after_many_appends() {
+        let fake = FakeS3::start().await;
+        let config = fake
+            .builder()
+            .compression(Compression::Gzip)
+            .build()
+            .await
+            .unwrap();
+        let config = Arc::new(config);
+        let (http_log_layer, guard) = HttpLogLayer::new(config.clone()).unwrap();
+        let output = http_log_layer.output.clone();
+        let name = output.read().await.name();
+        assert!(name.ends_with(".log.gz"));
+        for line in ["first", "second", "third"] {
+            output
+                .read()
+                .await
+                .append_to_buffer(Level::INFO, line.to_string())
+                .await;
+            HttpLogLayer::send_logs(config.clone(), output.clone(), http_log_layer.sink.as_ref())
+                .await
+                .unwrap();
+        }
+        guard.shutdown().await.unwrap();
+
+        let mut decoded = String::new();
+        MultiGzDecoder::new(fake.object(&name).unwrap().as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "first\nsecond\nthird\n");
+        let encodings: Vec<_> = fake
+            .requests()
+            .into_iter()
+            .filter(|r| r.method == "PUT")
+            .map(|r| r.headers.get("content-encoding").cloned())
+            .collect();
+        assert_eq!(encodings.len(), 3);
+        assert!(encodings[0].as_deref().unwrap().contains("gzip"));
+        assert!(
+            encodings[1..]
+                .iter()
+                .all(|e| !e.as_deref().unwrap_or_default().contains("gzip"))
+        );
+    }
+
+    #[tokio::test]
+    async fn zstd_objects_decompress_after_many_appends() {
+        let config = memory_config()
+            .compression(Compression::Zstd)
+            .build()
+            .await
+            .unwrap();
+        let config = Arc::new(config);
+        let sink = MemorySink::new();
+        let (http_log_layer, guard) =
+            HttpLogLayer::with_sink(config.clone(), sink.clone()).unwrap();
+        let output = http_log_layer.output.clone();
+        let name = output.read().await.name();
+        assert!(name.ends_with(".log.zst"));
+        for line in ["first", "second"] {
+            output
+                .read()
+                .await
+                .append_to_buffer(Level::INFO, line.to_string())
+                .await;
+            HttpLogLayer::send_logs(config.clone(), output.clone(), &sink)
+                .await
+                .unwrap();
+        }
+        guard.shutdown().await.unwrap();
+        let object = sink.object(&name).unwrap();
+        assert_eq!(
+            zstd::decode_all(object.as_slice()).unwrap(),
+            b"first\nsecond\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn flushes_keep_the_multipart_upload_open() {
+        let fake = FakeS3::start().await;
+        let config = fake
+            .builder()
+            .cron_interval_in_ms(60_000)
+            .upload_strategy(UploadStrategy::Multipart)
+            .build()
+            .await
+            .unwrap();
+        let (http_log_layer, guard) = HttpLogLayer::new(Arc::new(config)).unwrap();
+        let name = http_log_layer.output.read().await.name();
+        let flush_handle = http_log_layer.flush_handle();
+        let dispatch = Dispatch::new(tracing_subscriber::registry().with(http_log_layer));
+        for flush in 0..3 {
+            with_default(&dispatch, || tracing::info!(flush, "flushed"));
+            flush_handle.flush().await.unwrap();
+            assert_eq!(fake.open_uploads(), 1);
+            assert!(fake.object(&name).is_none());
+        }
+        guard.shutdown().await.unwrap();
+        assert_eq!(fake.open_uploads(), 0);
+        let object = String::from_utf8(fake.object(&name).unwrap()).unwrap();
+        assert_eq!(object.lines().count(), 3);
+        let started = fake
+            .requests()
+            .iter()
+            .filter(|r| r.method == "POST" && r.query.contains("uploads"))
+            .count();
+        assert_eq!(started, 1);
+        assert!(fake.requests().iter().all(|r| r.method != "GET"));
+    }
+
+    #[tokio::test]
+    async fn multipart_objects_are_completed_on_rotation_and_shutdown() {
+        let fake = FakeS3::start().await;
+        let config = fake
+            .builder()
+            .buffer_size_limit_kb(1)
+            .cron_interval_in_ms(60_000)
+            .upload_strategy(UploadStrategy::Multipart)
+            .build()
+            .await
+            .unwrap();
+        let config = Arc::new(config);
+        let (http_log_layer, guard) = HttpLogLayer::new(config.clone()).unwrap();
+        let output = http_log_layer.output.clone();
+        let first = output.read().await.name();
+        output
+            .read()
+            .await
+            .append_to_buffer(Level::INFO, "a".repeat(1_024 * 1_024 - 1))
+            .await;
+        HttpLogLayer::send_logs(config.clone(), output.clone(), http_log_layer.sink.as_ref())
+            .await
+            .unwrap();
+        let second = output.read().await.name();
+        assert_ne!(first, second);
+        assert_eq!(
+            fake.object(&first),
+            Some(format!("{}\n", "a".repeat(1_024 * 1_024 - 1)).into_bytes())
+        );
+
+        output
+            .read()
+            .await
+            .append_to_buffer(Level::INFO, "b".to_string())
+            .await;
+        HttpLogLayer::send_logs(config, output, http_log_layer.sink.as_ref())
+            .await
+            .unwrap();
+        assert_eq!(fake.object(&second), None);
+        guard.shutdown().await.unwrap();
+        assert_eq!(fake.object(&second).as_deref(), Some(&b"b\n"[..]));
+        assert_eq!(fake.open_uploads(), 0);
+    }
+}
+
+/// A minimal in-process S3 stand-in for tests.
+///
+/// Speaks just enough HTTP/1.1 to serve the `HeadObject`, `GetObject`, `PutObject` (including
+/// `x-amz-write-offset-bytes` appends) and multipart upload calls the layer makes, stores
+/// objects in memory and can be told to fail upcoming requests on purpose.
+#[cfg(test)]
+pub mod fake_s3 {
+    use crate::config::builder::TracingS3ConfigBuilder;
+    use crate::config::retry_policy::RetryPolicy;
+    use crate::config::tracing_s3_config::TracingS3Config;
+    use crate::sink::s3_sink::{S3Sink, UploadStrategy};
+    use aws_credential_types::Credentials;
+    use aws_sdk_s3::Client;
+    use aws_sdk_s3::config::retry::RetryConfig;
+    use aws_types::region::Region;
+    use percent_encoding::percent_decode_str;
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    pub const BUCKET: &str = "fake-bucket";
+
+    /// A request as seen by the fake server.
+    #[derive(Debug, Clone)]
+    pub struct RecordedRequest {
+        pub method: String,
+        pub key: String,
+        pub query: String,
+        pub headers: HashMap<String, String>,
+        pub body: Vec<u8>,
+    }
+
+    #[derive(Default)]
+    struct State {
+        objects: HashMap<String, Vec<u8>>,
+        uploads: HashMap<String, HashMap<i32, Vec<u8>>>,
+        next_upload_id: u64,
+        failures: VecDeque<Injected>,
+        requests: Vec<RecordedRequest>,
+    }
+
+    /// What happens to a request, queued by [`FakeS3::fail_next`] and friends.
+    enum Injected {
+        Pass,
+        Fail(u16, &'static str),
+        LoseResponse(u16, &'static str),
+    }
+
+    struct Response {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Response {
+        fn empty(status: u16) -> Self {
+            Self {
+                status,
+                headers: vec![],
+                body: vec![],
+            }
+        }
+
+        fn error(status: u16, code: &str) -> Self {
+            Self {
+                status,
+                headers: vec![("content-type".to_string(), "application/xml".to_string())],
+                body: format!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>{code}</Code><Message>{code}</Message><RequestId>fake</RequestId></Error>"
+                )
+                .into_bytes(),
+            }
+        }
+    }
+
+    /// Handle to a running fake S3 server.
+    #[derive(Clone)]
+    pub struct FakeS3 {
+        endpoint: String,
+        state: Arc<Mutex<State>>,
+    }
+
+    impl FakeS3 {
+        /// Binds to a random local port and starts serving in the background.
+        pub async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let endpoint = format!("http://{}", listener.local_addr().unwrap());
+            let state = Arc::new(Mutex::new(State::default()));
+            let accept_state = state.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(Self::serve(stream, accept_state.clone()));
+                }
+            });
+            Self { endpoint, state }
+        }
+
+        /// Returns the endpoint URL of the server.
+        pub fn endpoint(&self) -> &str {
+            &self.endpoint
+        }
+
+        /// Builds a client pointed at this server, with the SDK's own retries disabled.
+        pub fn client(&self) -> Client {
+            let credentials = Credentials::new("test", "test", None, None, "test");
+            let config = aws_sdk_s3::Config::builder()
+                .behavior_version_latest()
+                .credentials_provider(credentials)
+                .region(Region::new("us-east-1"))
+                .endpoint_url(&self.endpoint)
+                .force_path_style(true)
+                .retry_config(RetryConfig::disabled())
+                .build();
+            Client::from_conf(config)
+        }
+
+        /// Builds an appending [`S3Sink`] pointed at this server, using the same retry policy
+        /// as [`FakeS3::builder`].
+        pub fn sink(&self) -> S3Sink {
+            S3Sink::new(self.client(), BUCKET, Self::retry_policy())
+                .with_strategy(UploadStrategy::Append)
+        }
+
+        /// Returns the number of multipart uploads that were started but not completed or aborted.
+        pub fn open_uploads(&self) -> usize {
+            self.state.lock().unwrap().uploads.len()
+        }
+
+        fn retry_policy() -> RetryPolicy {
+            RetryPolicy::new(3, 1, 5, false).unwrap()
+        }
+
+        /// Returns a config builder pointed at this server, appending to objects of at most
+        /// 1 MB through a 1 MB buffer that is flushed every 50ms.
+        pub fn builder(&self) -> TracingS3ConfigBuilder {
+            TracingS3Config::builder()
+                .client(self.client())
+                .bucket(BUCKET)
+                .prefix("prefix")
+                .postfix("log")
+                .object_size_limit_mb(1)
+                .buffer_size_limit_kb(1_000)
+                .cron_interval_in_ms(50)
+                .shutdown_timeout_in_ms(5_000)
+                .retry_policy(Self::retry_policy())
+                .upload_strategy(UploadStrategy::Append)
+        }
+
+        /// Makes the next request fail with the given HTTP status and S3 error code.
+        pub fn fail_next(&self, status: u16, code: &'static str) {
+            self.state
+                .lock()
+                .unwrap()
+                .failures
+                .push_back(Injected::Fail(status, code));
+        }
+
+        /// Applies the next request, then answers it with the given HTTP status and S3 error
+        /// code, as if the response was lost on the way back.
+        pub fn lose_next_response(&self, status: u16, code: &'static str) {
+            self.state
+                .lock()
+                .unwrap()
+                .failures
+                .push_back(Injected::LoseResponse(status, code));
+        }
+
+        /// Lets the next request through, so a later [`FakeS3::fail_next`] hits a specific request.
+        pub fn pass_next(&self) {
+            self.state
+                .lock()
+                .unwrap()
+                .failures
+                .push_back(Injected::Pass);
+        }
+
+        /// Returns a copy of the object stored under `key`, if any.
+        pub fn object(&self, key: &str) -> Option<Vec<u8>> {
+            self.state.lock().unwrap().objects.get(key).cloned()
+        }
+
+        /// Returns all stored objects sorted by key.
+        pub fn objects(&self) -> Vec<(String, Vec<u8>)> {
+            let mut objects: Vec<_> = self
+                .state
+                .lock()
+                .unwrap()
+                .objects
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            objects.sort();
+            objects
+        }
+
+        /// Returns every request received so far.
+        pub fn requests(&self) -> Vec<RecordedRequest> {
+            self.state.lock().unwrap().requests.clone()
+        }
+
+        async fn serve(stream: TcpStream, state: Arc<Mutex<State>>) {
+            let mut stream = BufReader::new(stream);
+            loop {
+                let mut request_line = String::new();
+                match stream.read_line(&mut request_line).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => {}
+                }
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let target = parts.next().unwrap_or_default().to_string();
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                        return;
+                    }
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+                    }
+                }
+                let body = match Self::read_body(&mut stream, &headers).await {
+                    Some(body) => body,
+                    None => return,
+                };
+                let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+                let key = path
+                    .trim_start_matches('/')
+                    .trim_start_matches(BUCKET)
+                    .trim_start_matches('/');
+                let key = percent_decode_str(key).decode_utf8_lossy().into_owned();
+                let request = RecordedRequest {
+                    method,
+                    key,
+                    query: query.to_string(),
+                    headers,
+                    body,
+                };
+                let response = Self::handle(&state, request);
+                let mut raw = format!("HTTP/1.1 {} Fake\r\n", response.status);
+                let content_length = response
+                    .headers
+                    .iter()
+                    .find(|(name, _)| name == "content-length")
+                    .map(|(_, value)| value.clone())
+                    .unwrap_or(response.body.len().to_string());
+                raw.push_str(&format!("content-length: {content_length}\r\n"));
+                for (name, value) in response
+                    .headers
+                    .iter()
+                    .filter(|(n, _)| n != "content-length")
+                {
+                    raw.push_str(&format!("{name}: {value}\r\n"));
+                }
+                raw.push_str("\r\n");
+                let mut raw = raw.into_bytes();
+                raw.extend_from_slice(&response.body);
+                if stream.get_mut().write_all(&raw).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        async fn read_body(
+            stream: &mut BufReader<TcpStream>,
+            headers: &HashMap<String, String>,
+        ) -> Option<Vec<u8>> {
+            let mut raw = Vec::new();
+            if let Some(len) = headers.get("content-length") {
+                raw.resize(len.parse().ok()?, 0);
+                stream.read_exact(&mut raw).await.ok()?;
+            } else if headers
+                .get("transfer-encoding")
+                .is_some_and(|v| v.contains("chunked"))
+            {
+                raw = Self::read_chunks(stream).await?;
+            }
+            let aws_chunked = headers
+                .get("content-encoding")
+                .is_some_and(|v| v.contains("aws-chunked"));
+            if aws_chunked {
+                let mut reader = BufReader::new(raw.as_slice());
+                return Self::read_chunks(&mut reader).await;
+            }
+            Some(raw)
+        }
+
+        async fn read_chunks<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<Vec<u8>> {
+            let mut body = Vec::new();
+            loop {
+                let mut size_line = String::new();
+                reader.read_line(&mut size_line).await.ok()?;
+                let size_hex = size_line.trim().split(';').next()?;
+                let size = usize::from_str_radix(size_hex, 16).ok()?;
+                if size == 0 {
+                    // Trailers (e.g. the checksum) up to the terminating empty line.
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).await.ok()? == 0 || line.trim().is_empty() {
+                            return Some(body);
+                        }
+                    }
+                }
+                let mut chunk = vec![0; size + 2];
+                reader.read_exact(&mut chunk).await.ok()?;
+                chunk.truncate(size);
+                body.extend_from_slice(&chunk);
+            }
+        }
+
+        fn handle(state: &Arc<Mutex<State>>, request: RecordedRequest) -> Response {
+            let mut state = state.lock().unwrap();
+            state.requests.push(request.clone());
+            match state.failures.pop_front() {
+                Some(Injected::Fail(status, code)) => Response::error(status, code),
+                Some(Injected::LoseResponse(status, code)) => {
+                    Self::respond(&mut state, request);
+                    Response::error(status, code)
+                }
+                Some(Injected::Pass) | None => Self::respond(&mut state, request),
+            }
+        }
+
+        fn respond(state: &mut State, request: RecordedRequest) -> Response {
+            let query: HashMap<&str, &str> = request
+                .query
+                .split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+                .collect();
+            if let Some(response) = Self::handle_multipart(state, &request, &query) {
+                return response;
+            }
+            match request.method.as_str() {
+                "HEAD" => match state.objects.get(&request.key) {
+                    Some(object) => Response {
+                        status: 200,
+                        headers: vec![("content-length".to_string(), object.len().to_string())],
+                        body: vec![],
+                    },
+                    None => Response::empty(404),
+                },
+                "GET" => match state.objects.get(&request.key) {
+                    Some(object) => match Self::range(&request.headers, object.len()) {
+                        Some((start, end)) => Response {
+                            status: 206,
+                            headers: vec![(
+                                "content-range".to_string(),
+                                format!("bytes {start}-{}/{}", end - 1, object.len()),
+                            )],
+                            body: object[start..end].to_vec(),
+                        },
+                        None => Response {
+                            status: 200,
+                            headers: vec![],
+                            body: object.clone(),
+                        },
+                    },
+                    None => Response::error(404, "NoSuchKey"),
+                },
+                "PUT" => {
+                    let offset = request
+                        .headers
+                        .get("x-amz-write-offset-bytes")
+                        .and_then(|v| v.parse::<usize>().ok());
+                    match offset {
+                        Some(offset) => {
+                            let current = state.objects.get(&request.key).map_or(0, Vec::len);
+                            if offset != current {
+                                return Response::error(400, "InvalidWriteOffset");
+                            }
+                            state
+                                .objects
+                                .entry(request.key)
+                                .or_default()
+                                .extend_from_slice(&request.body);
+                        }
+                        None => {
+                            state.objects.insert(request.key, request.body);
+                        }
+                    }
+                    Response::empty(200)
+                }
+                _ => Response::error(405, "MethodNotAllowed"),
+            }
+        }
+
+        /// Parses a `Range: bytes=<first>-<last>` header into an end-exclusive range.
+        fn range(headers: &HashMap<String, String>, len: usize) -> Option<(usize, usize)> {
+            let (first, last) = headers
+                .get("range")?
+                .strip_prefix("bytes=")?
+                .split_once('-')?;
+            let last = last.parse::<usize>().ok()?.min(len.checked_sub(1)?);
+            Some((first.parse().ok()?, last + 1))
+        }
+
+        /// Handles CreateMultipartUpload, UploadPart, CompleteMultipartUpload and
+        /// AbortMultipartUpload. Returns `None` for any other request.
+        fn handle_multipart(
+            state: &mut State,
+            request: &RecordedRequest,
+            query: &HashMap<&str, &str>,
+        ) -> Option<Response> {
+            let xml = |body: String| Response {
+                status: 200,
+                headers: vec![("content-type".to_string(), "application/xml".to_string())],
+                body: format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>{body}").into_bytes(),
+            };
+            let upload_id = query.get("uploadId").map(|id| id.to_string());
+            let response = match (request.method.as_str(), upload_id) {
+                ("POST", None) if query.contains_key("uploads") => {
+                    state.next_upload_id += 1;
+                    let upload_id = format!("upload-{}", state.next_upload_id);
+                    state.uploads.insert(upload_id.clone(), HashMap::new());
+                    xml(format!(
+                        "<InitiateMultipartUploadResult><Bucket>{BUCKET}</Bucket><Key>{}</Key><UploadId>{upload_id}</UploadId></InitiateMultipartUploadResult>",
+                        request.key
+                    ))
+                }
+                ("PUT", Some(upload_id)) => {
+                    let part_number = query.get("partNumber")?.parse::<i32>().ok()?;
+                    let copy_source = request.headers.get("x-amz-copy-source").map(|source| {
+                        let source = source.trim_start_matches('/');
+                        let source = source.strip_prefix(&format!("{BUCKET}/")).unwrap_or(source);
+                        percent_decode_str(source).decode_utf8_lossy().into_owned()
+                    });
+                    let body = match &copy_source {
+                        Some(source) => match state.objects.get(source) {
+                            Some(object) => object.clone(),
+                            None => return Some(Response::error(404, "NoSuchKey")),
+                        },
+                        None => request.body.clone(),
+                    };
+                    let Some(parts) = state.uploads.get_mut(&upload_id) else {
+                        return Some(Response::error(404, "NoSuchUpload"));
+                    };
+                    parts.insert(part_number, body);
+                    match copy_source {
+                        Some(_) => xml(format!(
+                            "<CopyPartResult><ETag>\"etag-{part_number}\"</ETag></CopyPartResult>"
+                        )),
+                        None => Response {
+                            status: 200,
+                            headers: vec![("etag".to_string(), format!("\"etag-{part_number}\""))],
+                            body: vec![],
+                        },
+                    }
+                }
+                ("POST", Some(upload_id)) => {
+                    let Some(mut parts) = state.uploads.remove(&upload_id) else {
+                        return Some(Response::error(404, "NoSuchUpload"));
+                    };
+                    let body = String::from_utf8_lossy(&request.body).to_string();
+                    let mut object = Vec::new();
+                    for chunk in body.split("<PartNumber>").skip(1) {
+                        let part_number = chunk.split('<').next()?.parse::<i32>().ok()?;
+                        match parts.remove(&part_number) {
+                            Some(part) => object.extend_from_slice(&part),
+                            None => return Some(Response::error(400, "InvalidPart")),
+                        }
+                    }
+                    state.objects.insert(request.key.clone(), object);
+                    xml(format!(
+                        "<CompleteMultipartUploadResult><Bucket>{BUCKET}</Bucket><Key>{}</Key><ETag>\"etag\"</ETag></CompleteMultipartUploadResult>",
+                        request.key
+                    ))
+                }
+                ("DELETE", Some(upload_id)) => {
+                    state.uploads.remove(&upload_id);
+                    Response::empty(204)
+                }
+                _ => return None,
+            };
+            Some(response)
+        }
+    }
 }
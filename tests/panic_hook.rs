@@ -0,0 +1,58 @@
+//! The panic hook is process-wide, so it is tested in its own test binary.
+
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::Dispatch;
+use tracing::dispatcher::with_default;
+use tracing_s3::config::tracing_s3_config::TracingS3Config;
+use tracing_s3::layer::http_log_layer::HttpLogLayer;
+use tracing_s3::sink::memory_sink::MemorySink;
+use tracing_subscriber::layer::SubscriberExt;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn panic_hook_uploads_and_finalizes_the_panic_before_unwinding() {
+    let config = TracingS3Config::builder()
+        .bucket("memory-bucket")
+        .access_key_id("test")
+        .secret_access_key("test")
+        .cron_interval_in_ms(60_000)
+        .span_list(true)
+        .build()
+        .await
+        .unwrap();
+    let sink = MemorySink::new();
+    let (http_log_layer, guard) = HttpLogLayer::with_sink(Arc::new(config), sink.clone()).unwrap();
+    let name = http_log_layer.output.read().await.name();
+    http_log_layer.install_panic_hook(Duration::from_secs(5));
+    let dispatch = Dispatch::new(tracing_subscriber::registry().with(http_log_layer));
+    let panicked = tokio::task::spawn_blocking(move || {
+        with_default(&dispatch, || {
+            let _span = tracing::info_span!("job", id = 7).entered();
+            panic!("boom {}", 42);
+        })
+    })
+    .await;
+    assert!(panicked.unwrap_err().is_panic());
+    // The object is finalized right away, so the record is stored even without a shutdown.
+    assert_eq!(sink.finalized(), vec![name.clone()]);
+
+    let object = String::from_utf8(sink.object(&name).unwrap()).unwrap();
+    let records: Vec<Value> = object
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 1);
+    let record = &records[0];
+    assert_eq!(record["level"], "ERROR");
+    assert_eq!(record["event"]["message"], "boom 42");
+    assert!(
+        record["event"]["location"]
+            .as_str()
+            .unwrap()
+            .starts_with("tests/panic_hook.rs:")
+    );
+    assert!(record["event"]["backtrace"].is_string());
+    assert_eq!(record["spans"], json!([{ "name": "job", "id": 7 }]));
+    guard.shutdown().await.unwrap();
+}